@@ -39,6 +39,51 @@ fn share_bench(bench: &mut Bencher, _i: &()) {
     ));
 }
 
+/* share on 512/729 point transforms with each reduction,
+   the 32-bit prime reduces with % when plain, the 62-bit one with Barrett
+*/
+fn reduction_bench(bench: &mut Bencher, reduction: Reduction, p: u64, r2: u64, r3: u64) {
+    let mut pss = OclContext::with_reduction(&DeviceSelector::Env, BuildProfile::Release, reduction,
+        p, r2, r3, 512, 729, 51200, 512, 700).unwrap();
+
+    let mut rng = thread_rng();
+    let secrets: Vec<u64> = (0..51200).map(|_| rng.gen_range(0, p)).collect();
+
+    bench.iter(|| black_box(
+        pss.share(black_box(&secrets))
+    ));
+}
+
+fn plain_32_bench(bench: &mut Bencher, _i: &()) {
+    reduction_bench(bench, Reduction::Plain, 3224862721u64, 889378889u64, 388768380u64);
+}
+
+fn montgomery_32_bench(bench: &mut Bencher, _i: &()) {
+    reduction_bench(bench, Reduction::Montgomery, 3224862721u64, 889378889u64, 388768380u64);
+}
+
+fn plain_62_bench(bench: &mut Bencher, _i: &()) {
+    reduction_bench(bench, Reduction::Plain, 4610415792919412737u64, 1266473570726112470u64, 2230453091198852918u64);
+}
+
+fn montgomery_62_bench(bench: &mut Bencher, _i: &()) {
+    reduction_bench(bench, Reduction::Montgomery, 4610415792919412737u64, 1266473570726112470u64, 2230453091198852918u64);
+}
+
+// the same 32-bit prime on uint elements, half the memory traffic of plain_32_bench
+fn u32_bench(bench: &mut Bencher, _i: &()) {
+    let p = 3224862721u32;
+    let mut pss = OclContext::<u32>::new(p, 889378889u32, 388768380u32,
+        512, 729, 51200, 512, 700).unwrap();
+
+    let mut rng = thread_rng();
+    let secrets: Vec<u32> = (0..51200).map(|_| rng.gen_range(0, p)).collect();
+
+    bench.iter(|| black_box(
+        pss.share(black_box(&secrets))
+    ));
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
 	c.bench_functions(
 		"pss",
@@ -47,6 +92,24 @@ fn criterion_benchmark(c: &mut Criterion) {
         ],
         (),
 	);
+	c.bench_functions(
+		"reduction",
+        vec![
+            Fun::new("plain 32-bit", plain_32_bench),
+            Fun::new("montgomery 32-bit", montgomery_32_bench),
+            Fun::new("plain 62-bit", plain_62_bench),
+            Fun::new("montgomery 62-bit", montgomery_62_bench),
+        ],
+        (),
+	);
+	c.bench_functions(
+		"width",
+        vec![
+            Fun::new("u64 32-bit prime", plain_32_bench),
+            Fun::new("u32 32-bit prime", u32_bench),
+        ],
+        (),
+	);
 }
 
 criterion_group!{
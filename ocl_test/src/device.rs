@@ -0,0 +1,207 @@
+use std::env;
+use std::fmt;
+
+use ocl::{Platform, Device};
+use ocl::core;
+use ocl::enums::{DeviceInfo, DeviceInfoResult};
+use ocl::flags::DeviceType;
+
+/* PSS_OCL_DEVICE picks the device for DeviceSelector::Env:
+     "gpu", "cpu", "accelerator"   by device type
+     "3"                           by index into list_devices()
+     "platform:<name>"             by platform name substring
+     "<name>"                      by device name substring
+   matching is case insensitive
+*/
+pub const DEVICE_ENV: &str = "PSS_OCL_DEVICE";
+
+// one OpenCL device together with what we need to know to choose it
+#[derive(Clone, Debug)]
+pub struct DeviceCandidate {
+    // position in list_devices(), across all platforms
+    pub index: usize,
+    pub platform: Platform,
+    pub device: Device,
+
+    pub platform_name: String,
+    pub name: String,
+    pub vendor: String,
+    pub device_type: DeviceType,
+
+    pub compute_units: u64,
+    pub max_clock_mhz: u64,
+    pub global_mem_size: u64,
+    pub max_mem_alloc_size: u64,
+    pub local_mem_size: u64,
+    pub max_constant_buffer_size: u64,
+    pub max_work_group_size: u64,
+}
+
+impl DeviceCandidate {
+    pub fn is_type(&self, device_type: DeviceType) -> bool {
+        self.device_type.contains(device_type)
+    }
+}
+
+impl fmt::Display for DeviceCandidate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {} / {} ({:?}): {} compute units, {} MHz, {} MB global, {} MB max alloc, {} KB local, {} KB constant, work group {}",
+            self.index, self.platform_name, self.name, self.device_type,
+            self.compute_units, self.max_clock_mhz,
+            self.global_mem_size >> 20, self.max_mem_alloc_size >> 20,
+            self.local_mem_size >> 10, self.max_constant_buffer_size >> 10,
+            self.max_work_group_size)
+    }
+}
+
+pub enum DeviceSelector {
+    // the device with the most compute units, the old default
+    MostComputeUnits,
+    PlatformName(String),
+    DeviceName(String),
+    Type(DeviceType),
+    Index(usize),
+    // parse PSS_OCL_DEVICE, MostComputeUnits if unset
+    Env,
+    Custom(Box<dyn Fn(&DeviceCandidate) -> bool>),
+}
+
+impl DeviceSelector {
+
+    pub fn from_env() -> DeviceSelector {
+        match env::var(DEVICE_ENV) {
+            Ok(value) => DeviceSelector::parse(&value),
+            Err(_) => DeviceSelector::MostComputeUnits,
+        }
+    }
+
+    pub fn parse(value: &str) -> DeviceSelector {
+        let value = value.trim();
+        let lower = value.to_lowercase();
+        if let Ok(index) = value.parse::<usize>() {
+            return DeviceSelector::Index(index);
+        }
+        match lower.as_str() {
+            "gpu" => DeviceSelector::Type(DeviceType::GPU),
+            "cpu" => DeviceSelector::Type(DeviceType::CPU),
+            "accelerator" => DeviceSelector::Type(DeviceType::ACCELERATOR),
+            _ if lower.starts_with("platform:") => DeviceSelector::PlatformName(value[9..].to_string()),
+            _ if lower.starts_with("device:") => DeviceSelector::DeviceName(value[7..].to_string()),
+            _ => DeviceSelector::DeviceName(value.to_string()),
+        }
+    }
+
+    /* Among the candidates this selector accepts,
+    the one with the most compute units
+    */
+    pub fn select<'a>(&self, candidates: &'a [DeviceCandidate]) -> Option<&'a DeviceCandidate> {
+        if let DeviceSelector::Env = self {
+            return DeviceSelector::from_env().select(candidates);
+        }
+        candidates.iter()
+            .filter(|c| self.accepts(c))
+            .fold(None, |best: Option<&DeviceCandidate>, c| match best {
+                Some(b) if b.compute_units >= c.compute_units => Some(b),
+                _ => Some(c),
+            })
+    }
+
+    // every candidate this selector accepts, in list order
+    pub fn select_all<'a>(&self, candidates: &'a [DeviceCandidate]) -> Vec<&'a DeviceCandidate> {
+        if let DeviceSelector::Env = self {
+            return DeviceSelector::from_env().select_all(candidates);
+        }
+        candidates.iter().filter(|c| self.accepts(c)).collect()
+    }
+
+    fn accepts(&self, candidate: &DeviceCandidate) -> bool {
+        let contains = |haystack: &str, needle: &str| {
+            haystack.to_lowercase().contains(&needle.to_lowercase())
+        };
+        match self {
+            DeviceSelector::MostComputeUnits => true,
+            DeviceSelector::PlatformName(name) => contains(&candidate.platform_name, name),
+            DeviceSelector::DeviceName(name) => contains(&candidate.name, name),
+            DeviceSelector::Type(device_type) => candidate.is_type(*device_type),
+            DeviceSelector::Index(index) => candidate.index == *index,
+            DeviceSelector::Env => true,
+            DeviceSelector::Custom(f) => f(candidate),
+        }
+    }
+}
+
+impl Default for DeviceSelector {
+    fn default() -> DeviceSelector {
+        DeviceSelector::Env
+    }
+}
+
+impl fmt::Debug for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceSelector::MostComputeUnits => write!(f, "MostComputeUnits"),
+            DeviceSelector::PlatformName(name) => write!(f, "PlatformName({:?})", name),
+            DeviceSelector::DeviceName(name) => write!(f, "DeviceName({:?})", name),
+            DeviceSelector::Type(device_type) => write!(f, "Type({:?})", device_type),
+            DeviceSelector::Index(index) => write!(f, "Index({})", index),
+            DeviceSelector::Env => write!(f, "Env"),
+            DeviceSelector::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+/* Every device of every platform,
+empty if no OpenCL platform is installed
+*/
+pub fn list_devices() -> Vec<DeviceCandidate> {
+    let mut candidates = Vec::new();
+    for platform in list_platforms() {
+        let devices = match Device::list_all(&platform) {
+            Ok(devices) => devices,
+            Err(_) => continue,
+        };
+        let platform_name = platform.name().unwrap_or_default();
+        for device in devices {
+            let device_type = match device.info(DeviceInfo::Type) {
+                Ok(DeviceInfoResult::Type(t)) => t,
+                _ => DeviceType::DEFAULT,
+            };
+            candidates.push(DeviceCandidate {
+                index: candidates.len(),
+                platform: platform,
+                device: device,
+
+                platform_name: platform_name.clone(),
+                name: device.name().unwrap_or_default(),
+                vendor: device.vendor().unwrap_or_default(),
+                device_type: device_type,
+
+                compute_units: device_info_u64(&device, DeviceInfo::MaxComputeUnits),
+                max_clock_mhz: device_info_u64(&device, DeviceInfo::MaxClockFrequency),
+                global_mem_size: device_info_u64(&device, DeviceInfo::GlobalMemSize),
+                max_mem_alloc_size: device_info_u64(&device, DeviceInfo::MaxMemAllocSize),
+                local_mem_size: device_info_u64(&device, DeviceInfo::LocalMemSize),
+                max_constant_buffer_size: device_info_u64(&device, DeviceInfo::MaxConstantBufferSize),
+                max_work_group_size: device_info_u64(&device, DeviceInfo::MaxWorkGroupSize),
+            });
+        }
+    }
+    candidates
+}
+
+/* Platform::list() panics when clGetPlatformIDs fails, as it does with
+an ICD loader but no platform (CL_PLATFORM_NOT_FOUND_KHR), that is no platform here
+*/
+fn list_platforms() -> Vec<Platform> {
+    match core::get_platform_ids() {
+        Ok(ids) => ids.into_iter().map(Platform::new).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// numeric device info, 0 if the driver does not report it
+pub fn device_info_u64(device: &Device, info: DeviceInfo) -> u64 {
+    device.info(info)
+        .map(|r| r.to_string().parse().unwrap_or(0))
+        .unwrap_or(0)
+}
@@ -0,0 +1,88 @@
+use std::fmt;
+
+use ocl::enums::DeviceInfo;
+
+use crate::device::DeviceCandidate;
+use crate::elem::OclElem;
+
+/* What a device offers next to what a context of given parameters needs
+   int64:      every kernel indexes with ulong, full profile devices always have it,
+               embedded profile ones need cles_khr_int64
+   local mem:  the _group NTT kernels hold one poly of the larger transform in __local,
+               without room KernelMode::Auto stays per work item
+   work group: the _group kernels want degree / radix items per group,
+               fewer still work but loop over the butterflies
+*/
+#[derive(Clone, Debug)]
+pub struct Diagnostics {
+    pub platform: String,
+    pub device: String,
+
+    pub int64: bool,
+    pub local_mem_size: u64,
+    pub local_mem_needed: u64,
+    pub max_work_group_size: u64,
+    pub work_group_needed: u64,
+}
+
+impl Diagnostics {
+
+    // the kernels build and run, maybe without the _group variants
+    pub fn is_supported(&self) -> bool {
+        self.int64
+    }
+
+    // KernelMode::WorkGroup fits the device at full width
+    pub fn supports_work_group(&self) -> bool {
+        self.local_mem_needed <= self.local_mem_size
+            && self.work_group_needed <= self.max_work_group_size
+    }
+
+    // everything the device falls short of, empty if nothing
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !self.int64 {
+            problems.push("no 64-bit integers (embedded profile without cles_khr_int64)".to_string());
+        }
+        if self.local_mem_needed > self.local_mem_size {
+            problems.push(format!("{} bytes of local memory, the work group NTT kernels need {}",
+                self.local_mem_size, self.local_mem_needed));
+        }
+        if self.work_group_needed > self.max_work_group_size {
+            problems.push(format!("work groups of at most {}, the work group NTT kernels want {}",
+                self.max_work_group_size, self.work_group_needed));
+        }
+        problems
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let problems = self.problems();
+        if problems.is_empty() {
+            write!(f, "{} / {}: ok", self.platform, self.device)
+        } else {
+            write!(f, "{} / {}: {}", self.platform, self.device, problems.join(", "))
+        }
+    }
+}
+
+/* Checks device against a context of elements T with NTT degrees degree2 and degree3,
+needs no context, so it also explains devices OclContext::new refuses
+*/
+pub fn diagnose<T: OclElem>(device: &DeviceCandidate, degree2: usize, degree3: usize) -> Diagnostics {
+    let info = |info: DeviceInfo| device.device.info(info).map(|r| r.to_string()).unwrap_or_default();
+    let embedded = info(DeviceInfo::Profile).contains("EMBEDDED");
+    let int64 = !embedded || info(DeviceInfo::Extensions).contains("cles_khr_int64");
+
+    Diagnostics {
+        platform: device.platform_name.clone(),
+        device: device.name.clone(),
+
+        int64: int64,
+        local_mem_size: device.local_mem_size,
+        local_mem_needed: (degree2.max(degree3) * T::bytes()) as u64,
+        max_work_group_size: device.max_work_group_size,
+        work_group_needed: (degree2 / 2).max(degree3 / 3) as u64,
+    }
+}
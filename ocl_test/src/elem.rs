@@ -0,0 +1,132 @@
+use std::borrow::Cow;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::mem::size_of;
+
+use ocl::traits::OclPrm;
+use ocl::prm::Ulong2;
+
+/* Element types of OclContext, as the kernels see them (ELEM_BITS in kernel.rs)
+   u32:  one uint, primes below 2^32, half the traffic of u64
+   u64:  one ulong, primes below 2^62
+   u128: a ulong2 limb pair, low limb first, primes below 2^127,
+         the kernels emulate the arithmetic in Montgomery form
+   device buffers hold LIMBS Limbs per element
+*/
+pub trait OclElem: Copy + Debug + PartialEq + Eq + Hash + Send + Sync + 'static {
+    const BITS: u32;
+    const LIMBS: usize;
+    // exclusive bound on the prime
+    const MAX_PRIME: u128;
+
+    // one element as a scalar kernel argument
+    type Arg: OclPrm;
+    // the type of device buffers
+    type Limb: OclPrm;
+
+    fn arg(self) -> Self::Arg;
+    fn to_u128(self) -> u128;
+    fn from_u128(x: u128) -> Self;
+
+    fn to_limbs(values: &[Self]) -> Cow<'_, [Self::Limb]>;
+    fn from_limbs(limbs: &[Self::Limb]) -> Vec<Self>;
+
+    // device bytes per element
+    fn bytes() -> usize {
+        Self::LIMBS * size_of::<Self::Limb>()
+    }
+}
+
+impl OclElem for u32 {
+    const BITS: u32 = 32;
+    const LIMBS: usize = 1;
+    const MAX_PRIME: u128 = 1 << 32;
+
+    type Arg = u32;
+    type Limb = u32;
+
+    fn arg(self) -> u32 {
+        self
+    }
+
+    fn to_u128(self) -> u128 {
+        self as u128
+    }
+
+    fn from_u128(x: u128) -> u32 {
+        x as u32
+    }
+
+    fn to_limbs(values: &[u32]) -> Cow<'_, [u32]> {
+        Cow::Borrowed(values)
+    }
+
+    fn from_limbs(limbs: &[u32]) -> Vec<u32> {
+        limbs.to_vec()
+    }
+}
+
+impl OclElem for u64 {
+    const BITS: u32 = 64;
+    const LIMBS: usize = 1;
+    const MAX_PRIME: u128 = 1 << 62;
+
+    type Arg = u64;
+    type Limb = u64;
+
+    fn arg(self) -> u64 {
+        self
+    }
+
+    fn to_u128(self) -> u128 {
+        self as u128
+    }
+
+    fn from_u128(x: u128) -> u64 {
+        x as u64
+    }
+
+    fn to_limbs(values: &[u64]) -> Cow<'_, [u64]> {
+        Cow::Borrowed(values)
+    }
+
+    fn from_limbs(limbs: &[u64]) -> Vec<u64> {
+        limbs.to_vec()
+    }
+}
+
+impl OclElem for u128 {
+    const BITS: u32 = 128;
+    const LIMBS: usize = 2;
+    const MAX_PRIME: u128 = 1 << 127;
+
+    type Arg = Ulong2;
+    type Limb = u64;
+
+    fn arg(self) -> Ulong2 {
+        Ulong2::new(self as u64, (self >> 64) as u64)
+    }
+
+    fn to_u128(self) -> u128 {
+        self
+    }
+
+    fn from_u128(x: u128) -> u128 {
+        x
+    }
+
+    fn to_limbs(values: &[u128]) -> Cow<'_, [u64]> {
+        let mut limbs = Vec::with_capacity(2 * values.len());
+        for v in values {
+            limbs.push(*v as u64);
+            limbs.push((*v >> 64) as u64);
+        }
+        Cow::Owned(limbs)
+    }
+
+    fn from_limbs(limbs: &[u64]) -> Vec<u128> {
+        limbs.chunks(2)
+            .map(|l| l[0] as u128 | (l[1] as u128) << 64)
+            .collect()
+    }
+}
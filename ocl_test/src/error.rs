@@ -0,0 +1,157 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+
+use crate::device::DeviceCandidate;
+use crate::diagnostics::Diagnostics;
+
+// lines of generated source shown around every line the compiler flags
+const SOURCE_CONTEXT: usize = 2;
+
+/* Why an OclContext could not be set up
+   Parameters:  the context parameters are outside what the kernels handle,
+                checked before any device is touched
+   NoDevice:    no OpenCL platform is installed, or no device matches the selector
+   Unsupported: the device lacks what the kernels need, see diagnostics::diagnose
+   Build:       the kernels did not compile, with the compiler output and
+                the generated source around every line it complains about
+   Ocl:         any other OpenCL call while setting up failed
+*/
+#[derive(Debug)]
+pub enum OclError {
+    Parameters {
+        reason: String,
+    },
+    NoDevice {
+        selector: String,
+        // every device found, empty without a platform
+        available: Vec<String>,
+    },
+    Unsupported {
+        diagnostics: Diagnostics,
+    },
+    Build {
+        platform: String,
+        device: String,
+        options: String,
+        log: String,
+        source: Vec<SourceLine>,
+    },
+    Ocl {
+        platform: String,
+        device: String,
+        error: ocl::Error,
+    },
+}
+
+// one line of program::kernel_source(), numbered from 1 like compiler output
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLine {
+    pub number: usize,
+    pub text: String,
+    // the compiler reported an error on this line
+    pub flagged: bool,
+}
+
+impl OclError {
+
+    pub(crate) fn ocl(device: &DeviceCandidate, error: ocl::Error) -> OclError {
+        OclError::Ocl {
+            platform: device.platform_name.clone(),
+            device: device.name.clone(),
+            error: error,
+        }
+    }
+
+    /* log is the compiler output as ocl reports it,
+    source the program it was built from
+    */
+    pub(crate) fn build(device: &DeviceCandidate, options: &str, log: String, source: &str) -> OclError {
+        let flagged = flagged_lines(&log);
+        let lines: Vec<&str> = source.lines().collect();
+        let mut shown = BTreeSet::new();
+        for &number in flagged.iter() {
+            let first = number.saturating_sub(SOURCE_CONTEXT).max(1);
+            let last = (number + SOURCE_CONTEXT).min(lines.len());
+            shown.extend(first..=last);
+        }
+        OclError::Build {
+            platform: device.platform_name.clone(),
+            device: device.name.clone(),
+            options: options.to_string(),
+            log: log,
+            source: shown.into_iter()
+                .map(|number| SourceLine {
+                    number: number,
+                    text: lines[number - 1].to_string(),
+                    flagged: flagged.contains(&number),
+                })
+                .collect(),
+        }
+    }
+}
+
+/* Line numbers of errors in a build log, from the clang style
+"<source>:215:12: error: ..." most drivers print
+*/
+fn flagged_lines(log: &str) -> BTreeSet<usize> {
+    let mut flagged = BTreeSet::new();
+    for line in log.lines().filter(|l| l.contains("error")) {
+        let fields: Vec<&str> = line.split(':').map(|f| f.trim()).collect();
+        for pair in fields.windows(2) {
+            if let (Ok(number), Ok(_)) = (pair[0].parse::<usize>(), pair[1].parse::<usize>()) {
+                if number > 0 {
+                    flagged.insert(number);
+                }
+                break;
+            }
+        }
+    }
+    flagged
+}
+
+impl fmt::Display for OclError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OclError::Parameters { reason } => {
+                write!(f, "parameters not supported on OpenCL: {}", reason)
+            }
+            OclError::NoDevice { selector, available } => {
+                if available.is_empty() {
+                    return write!(f, "no OpenCL platform found");
+                }
+                writeln!(f, "no OpenCL device matches {}, available:", selector)?;
+                for device in available {
+                    writeln!(f, "    {}", device)?;
+                }
+                Ok(())
+            }
+            OclError::Unsupported { diagnostics } => {
+                write!(f, "device not supported: {}", diagnostics)
+            }
+            OclError::Build { platform, device, options, log, source } => {
+                writeln!(f, "kernel build failed on {} / {}", platform, device)?;
+                writeln!(f, "options: {}", options)?;
+                writeln!(f, "log:\n{}", log.trim_end())?;
+                if !source.is_empty() {
+                    writeln!(f, "source:")?;
+                }
+                let mut last = 0;
+                for line in source {
+                    if last != 0 && line.number != last + 1 {
+                        writeln!(f, "    ...")?;
+                    }
+                    let marker = if line.flagged { ">" } else { " " };
+                    writeln!(f, "{} {:5} | {}", marker, line.number, line.text)?;
+                    last = line.number;
+                }
+                Ok(())
+            }
+            OclError::Ocl { platform, device, error } => {
+                write!(f, "OpenCL error on {} / {}: {}", platform, device, error)
+            }
+        }
+    }
+}
+
+impl Error for OclError {}
@@ -0,0 +1,180 @@
+use pss_compare::{PackedSecretSharing, SharingBackend};
+
+#[cfg(feature = "opencl")]
+use crate::{OclContext, OclError};
+
+/* Element types PssContext can share on either engine,
+   the CPU engine of pss_compare computes in $wide
+   not u128: OclContext<u128> takes primes up to 2^127, but the CPU engine
+   multiplies in u128 and is only correct for primes below 2^64
+*/
+pub trait PssElem: Copy + 'static {
+    // OclContext::new, or why there is none
+    fn opencl(prime: Self, root2: Self, root3: Self,
+              degree2: usize, degree3: usize, total_len: usize, packing_len: usize, num_shares: usize)
+              -> Result<Box<dyn SharingBackend<Elem = Self>>, String>;
+
+    fn cpu(prime: Self, root2: Self, root3: Self,
+           degree2: usize, degree3: usize, total_len: usize, packing_len: usize, num_shares: usize)
+           -> Box<dyn SharingBackend<Elem = Self>>;
+}
+
+macro_rules! pss_elem {
+    ($elem: ty, $wide: ty) => (
+        impl PssElem for $elem {
+            #[cfg(feature = "opencl")]
+            fn opencl(prime: $elem, root2: $elem, root3: $elem,
+                      degree2: usize, degree3: usize, total_len: usize, packing_len: usize, num_shares: usize)
+                      -> Result<Box<dyn SharingBackend<Elem = $elem>>, String> {
+                match OclContext::<$elem>::new(prime, root2, root3, degree2, degree3, total_len, packing_len, num_shares) {
+                    Ok(context) => Ok(Box::new(context)),
+                    // no usable device or a prime past the kernels is what the fallback is for,
+                    // anything else is worth a word
+                    Err(e @ OclError::Parameters { .. }) | Err(e @ OclError::NoDevice { .. })
+                        | Err(e @ OclError::Unsupported { .. }) => Err(e.to_string()),
+                    Err(e) => {
+                        eprintln!("OpenCL failed, sharing on the CPU: {}", e);
+                        Err(e.to_string())
+                    }
+                }
+            }
+
+            #[cfg(not(feature = "opencl"))]
+            fn opencl(_prime: $elem, _root2: $elem, _root3: $elem,
+                      _degree2: usize, _degree3: usize, _total_len: usize, _packing_len: usize, _num_shares: usize)
+                      -> Result<Box<dyn SharingBackend<Elem = $elem>>, String> {
+                Err("built without the opencl feature".to_string())
+            }
+
+            fn cpu(prime: $elem, root2: $elem, root3: $elem,
+                   degree2: usize, degree3: usize, total_len: usize, packing_len: usize, num_shares: usize)
+                   -> Box<dyn SharingBackend<Elem = $elem>> {
+                Box::new(PackedSecretSharing::<$wide>::new(prime as $wide, root2 as $wide, root3 as $wide,
+                    degree2, degree3, total_len, packing_len, num_shares))
+            }
+        }
+    )
+}
+
+pss_elem!(u32, u64);
+pss_elem!(u64, u128);
+
+/* OclContext on the device from PSS_OCL_DEVICE when there is one,
+   otherwise the same NTT pipeline on the CPU (pss_compare::PackedSecretSharing),
+   so one binary runs on GPU nodes, GPU-less nodes and build servers alike
+   u32 and u64 elements only (see PssElem), primes above 2^62 need OclContext<u128>
+   directly and have no CPU fallback
+*/
+pub struct PssContext<T: PssElem = u64> {
+    backend: Box<dyn SharingBackend<Elem = T>>,
+    // why OpenCL is not used, None if it is
+    fallback: Option<String>,
+}
+
+impl<T: PssElem> PssContext<T> {
+
+    pub fn new(prime: T, root2: T, root3: T,
+               degree2: usize, degree3: usize, total_len: usize, packing_len: usize, num_shares: usize) -> PssContext<T> {
+        match T::opencl(prime, root2, root3, degree2, degree3, total_len, packing_len, num_shares) {
+            Ok(backend) => PssContext {
+                backend: backend,
+                fallback: None,
+            },
+            Err(reason) => PssContext::with_cpu(prime, root2, root3,
+                degree2, degree3, total_len, packing_len, num_shares, reason),
+        }
+    }
+
+    // the CPU engine even if OpenCL is there
+    pub fn new_cpu(prime: T, root2: T, root3: T,
+                   degree2: usize, degree3: usize, total_len: usize, packing_len: usize, num_shares: usize) -> PssContext<T> {
+        PssContext::with_cpu(prime, root2, root3, degree2, degree3, total_len, packing_len, num_shares,
+            "CPU engine requested".to_string())
+    }
+
+    fn with_cpu(prime: T, root2: T, root3: T,
+                degree2: usize, degree3: usize, total_len: usize, packing_len: usize, num_shares: usize, reason: String) -> PssContext<T> {
+        PssContext {
+            backend: T::cpu(prime, root2, root3, degree2, degree3, total_len, packing_len, num_shares),
+            fallback: Some(reason),
+        }
+    }
+
+    pub fn is_opencl(&self) -> bool {
+        self.fallback.is_none()
+    }
+
+    pub fn fallback_reason(&self) -> Option<&str> {
+        self.fallback.as_ref().map(|r| r.as_str())
+    }
+}
+
+impl<T: PssElem> SharingBackend for PssContext<T> {
+    type Elem = T;
+
+    fn share(&mut self, secrets: &[T]) -> Vec<Vec<T>> {
+        self.backend.share(secrets)
+    }
+
+    fn reconstruct(&mut self, shares: &[Vec<T>], shares_point: &[T]) -> Vec<T> {
+        self.backend.reconstruct(shares, shares_point)
+    }
+
+    fn prime(&self) -> T {
+        self.backend.prime()
+    }
+
+    fn share_point(&self, party: usize) -> T {
+        self.backend.share_point(party)
+    }
+
+    fn degree2(&self) -> usize {
+        self.backend.degree2()
+    }
+
+    fn degree3(&self) -> usize {
+        self.backend.degree3()
+    }
+
+    fn total_len(&self) -> usize {
+        self.backend.total_len()
+    }
+
+    fn packing_len(&self) -> usize {
+        self.backend.packing_len()
+    }
+
+    fn num_shares(&self) -> usize {
+        self.backend.num_shares()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2^62 < P < 2^63: past the 64 bit kernels, in range of the CPU engine
+    const P: u64 = 4611686018432467969;
+    // of order 512 and 729
+    const ROOT2: u64 = 4313951629608107435;
+    const ROOT3: u64 = 4253006353022761731;
+
+    #[cfg(feature = "opencl")]
+    #[test]
+    fn large_prime_is_a_parameter_error() {
+        match OclContext::<u64>::new(P, ROOT2, ROOT3, 512, 729, 400, 100, 600) {
+            Err(OclError::Parameters { .. }) => {}
+            other => panic!("expected OclError::Parameters, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn large_prime_shares_on_the_cpu() {
+        let mut pss = PssContext::<u64>::new(P, ROOT2, ROOT3, 512, 729, 400, 100, 600);
+        assert!(!pss.is_opencl());
+        let secrets: Vec<u64> = (0..400).map(|i| (i * i + 1) << 40).collect();
+        let shares = pss.share(&secrets);
+        let points: Vec<u64> = (20..560).map(|j| pss.share_point(j)).collect();
+        assert_eq!(pss.reconstruct(&shares[20..560], &points), secrets);
+    }
+}
@@ -1,20 +1,305 @@
-pub static NTT_INVERSE2: &str = r#"
+/* Build options, passed as -D by program::KernelParams,
+   L, log2(L) and the prime P are runtime args of the kernels
+   ELEM_BITS                 32 for uint (NARROW), 64 for ulong elements,
+                             128 for ulong2 limb pairs (WIDE)
+   TRIGITS                   trigit count of the radix 3 length
+   TWIDDLE                   __constant when the per-stage twiddle tables
+                             (layout in pss_compare::twiddle) fit, __global otherwise
+   SMALL_PRIME               P < 2^32, mul_mod is the plain product
+   BARRETT_BITS, BARRETT_MU  otherwise the Barrett constants of P for mul_mod
+   MONTGOMERY                mul_mod is a Montgomery product with MONT_PINV and MONT_R2,
+                             twiddles, L_inv and coeffs are uploaded in Montgomery form
+   MONT_PINV_LO/HI, MONT_R2_LO/HI, WIDE_MASK_LO/HI
+                             the same for 128 bit builds, which are always MONTGOMERY,
+                             and the mask of the random limbs
+   PSS_DEBUG                 NTT kernels take two more args and record every
+                             butterfly output, TRACE_CAPACITY records at most
+*/
+
+pub static PSS_TRACE: &str = r#"
+
+/* Trace records of 4 ulongs: poly, stage, index, value after the stage
+   stage is the butterfly span, m for radix 2 and 3i for radix 3
+   release builds compile TRACE to nothing and drop the args
+*/
+#ifdef PSS_DEBUG
+#define TRACE_ARGS , __global ulong* trace, __global uint* trace_len
+#define TRACE_PASS , trace, trace_len
+#define TRACE(poly, stage, index, value) { \
+	uint slot = atomic_inc(trace_len); \
+	if (slot < TRACE_CAPACITY) { \
+		trace[4*slot] = (poly); \
+		trace[4*slot+1] = (stage); \
+		trace[4*slot+2] = (index); \
+		trace[4*slot+3] = (value); \
+	} \
+}
+#else
+#define TRACE_ARGS
+#define TRACE_PASS
+#define TRACE(poly, stage, index, value)
+#endif
+"#;
+
+pub static PSS_ARITH: &str = r#"
+
+/* Element type from ELEM_BITS, indices stay ulong
+   32:  uint, P < 2^32, products in ulong, never MONTGOMERY
+   64:  ulong, P < 2^62
+   128: ulong2 limb pair, x the low and y the high limb, P < 2^127,
+        always MONTGOMERY with R = 2^128
+*/
+#if ELEM_BITS == 128
+#define WIDE
+typedef ulong2 elem;
+#elif ELEM_BITS == 32
+#define NARROW
+typedef uint elem;
+#else
+typedef ulong elem;
+#endif
+
+#ifdef WIDE
+
+inline elem elem_from (const ulong a)
+{
+	return (ulong2)(a, 0);
+}
+
+// a 128 bit input is taken as given, the host keeps it below P
+inline elem elem_reduce (const elem a, const elem P)
+{
+	return a;
+}
+
+// a >= b
+inline int ge2 (const ulong2 a, const ulong2 b)
+{
+	return a.y > b.y || (a.y == b.y && a.x >= b.x);
+}
+
+inline ulong2 add2 (const ulong2 a, const ulong2 b)
+{
+	ulong const lo = a.x + b.x;
+	return (ulong2)(lo, a.y + b.y + (lo < a.x));
+}
+
+inline ulong2 sub2 (const ulong2 a, const ulong2 b)
+{
+	return (ulong2)(a.x - b.x, a.y - b.y - (a.x < b.x));
+}
+
+// the 256 bit product a * b as (lo, hi)
+inline void mul_full (const ulong2 a, const ulong2 b, ulong2* lo, ulong2* hi)
+{
+	ulong const p00 = a.x * b.x;
+	ulong const c0 = mul_hi(a.x, b.x);
+	ulong const p01 = a.x * b.y;
+	ulong const p10 = a.y * b.x;
+
+	ulong c1 = c0 + p01;
+	ulong k1 = c1 < p01;
+	c1 += p10;
+	k1 += c1 < p10;
+
+	ulong c2 = mul_hi(a.x, b.y) + mul_hi(a.y, b.x);
+	ulong k2 = c2 < mul_hi(a.y, b.x);
+	ulong const p11 = a.y * b.y;
+	c2 += p11;
+	k2 += c2 < p11;
+	c2 += k1;
+	k2 += c2 < k1;
+
+	*lo = (ulong2)(p00, c1);
+	*hi = (ulong2)(c2, mul_hi(a.y, b.y) + k2);
+}
+
+inline elem add_mod (const elem a, const elem b, const elem P)
+{
+	elem const r = add2(a, b);
+	return ge2(r, P) ? sub2(r, P) : r;
+}
+
+inline elem sub_mod (const elem a, const elem b, const elem P)
+{
+	return ge2(a, b) ? sub2(a, b) : sub2(add2(a, P), b);
+}
+
+/* a * b * 2^-128 mod P, REDC with MONT_PINV_LO/HI = -P^-1 mod 2^128
+   the high limbs of the products are below P, their sum below 2^128
+*/
+inline elem mul_mod (const elem a, const elem b, const elem P)
+{
+	ulong2 lo, hi;
+	mul_full(a, b, &lo, &hi);
+
+	// m = lo * -P^-1 mod 2^128, only the low half of the product
+	ulong2 const pinv = (ulong2)(MONT_PINV_LO, MONT_PINV_HI);
+	ulong2 const m = (ulong2)(lo.x * pinv.x, mul_hi(lo.x, pinv.x) + lo.x * pinv.y + lo.y * pinv.x);
+
+	ulong2 mp_lo, mp_hi;
+	mul_full(m, P, &mp_lo, &mp_hi);
+
+	// lo + mp_lo is 0 mod 2^128, it carries unless lo is 0
+	elem const t = add2(add2(hi, mp_hi), elem_from((lo.x | lo.y) != 0));
+	return ge2(t, P) ? sub2(t, P) : t;
+}
+
+// MONT_R2_LO/HI = 2^256 mod P
+inline elem to_mont (const elem a, const elem P)
+{
+	return mul_mod(a, (ulong2)(MONT_R2_LO, MONT_R2_HI), P);
+}
+
+inline elem from_mont (const elem a, const elem P)
+{
+	return mul_mod(a, elem_from(1), P);
+}
+
+#elif defined(NARROW)
+
+inline elem elem_from (const ulong a)
+{
+	return (uint) a;
+}
+
+inline elem elem_reduce (const elem a, const elem P)
+{
+	return a % P;
+}
+
+// a + b wraps for P above 2^31, then it is above P as well
+inline elem add_mod (const elem a, const elem b, const elem P)
+{
+	elem const r = a + b;
+	return (r < a || r >= P) ? r - P : r;
+}
+
+inline elem sub_mod (const elem a, const elem b, const elem P)
+{
+	return a >= b ? a - b : a + P - b;
+}
+
+inline elem mul_mod (const elem a, const elem b, const elem P)
+{
+	return (uint) ((ulong) a * b % P);
+}
+
+#define to_mont(a, P) (a)
+#define from_mont(a, P) (a)
+
+#else
+
+inline elem elem_from (const ulong a)
+{
+	return a;
+}
+
+inline elem elem_reduce (const elem a, const elem P)
+{
+	return a % P;
+}
+
+// a + b < 2P and a + P < 2^64 below 2^62
+inline elem add_mod (const elem a, const elem b, const elem P)
+{
+	elem const r = a + b;
+	return r >= P ? r - P : r;
+}
+
+inline elem sub_mod (const elem a, const elem b, const elem P)
+{
+	return a >= b ? a - b : a + P - b;
+}
+
+/* a * b mod P for a, b < P, P is the prime the constants were built for
+   MONTGOMERY: a * b * 2^-64 mod P, REDC with MONT_PINV = -P^-1 mod 2^64,
+               the product of a Montgomery form x * 2^64 and a plain value is plain,
+               of two Montgomery forms again a Montgomery form
+   SMALL_PRIME (P < 2^32): the product fits in a ulong
+   otherwise Barrett on the 128 bit product from mul_hi, P < 2^62,
+   BARRETT_BITS = n with 2^(n-1) <= P < 2^n, BARRETT_MU = floor(2^2n / P)
+*/
+#if defined(MONTGOMERY)
+
+inline ulong mul_mod (const ulong a, const ulong b, const ulong P)
+{
+	ulong const lo = a * b;
+	ulong const hi = mul_hi(a, b);
+
+	// lo + m * P is 0 mod 2^64, it carries unless lo is 0
+	ulong const m = lo * MONT_PINV;
+	ulong const t = hi + mul_hi(m, P) + (lo != 0);
+	return t >= P ? t - P : t;
+}
+
+// MONT_R2 = 2^128 mod P
+inline ulong to_mont (const ulong a, const ulong P)
+{
+	return mul_mod(a, MONT_R2, P);
+}
+
+inline ulong from_mont (const ulong a, const ulong P)
+{
+	return mul_mod(a, 1, P);
+}
+
+#elif defined(SMALL_PRIME)
+
+inline ulong mul_mod (const ulong a, const ulong b, const ulong P)
+{
+	return a * b % P;
+}
+
+#else
+
+inline ulong mul_mod (const ulong a, const ulong b, const ulong P)
+{
+	ulong const lo = a * b;
+	ulong const hi = mul_hi(a, b);
+
+	// q = ((x >> (n-1)) * mu) >> (n+1), at most 2 below x / P
+	ulong const x1 = (hi << (65 - BARRETT_BITS)) | (lo >> (BARRETT_BITS - 1));
+	ulong const q_lo = x1 * BARRETT_MU;
+	ulong const q_hi = mul_hi(x1, (ulong) BARRETT_MU);
+	ulong const q = (q_hi << (63 - BARRETT_BITS)) | (q_lo >> (BARRETT_BITS + 1));
+
+	// x - q * P < 3P, the low words suffice
+	ulong r = lo - q * P;
+	while (r >= P) {
+		r -= P;
+	}
+	return r;
+}
+
+#endif
+
+// data is plain outside of Montgomery builds
+#ifndef MONTGOMERY
+#define to_mont(a, P) (a)
+#define from_mont(a, P) (a)
+#endif
+
+#endif
+"#;
+
+pub static NTT_RADIX2: &str = r#"
 
 void radix2_bitreverse (
-	__global ulong* source,
+	__global elem* source,
   	const ulong base,
   	const ulong L)
 {
 	int j = 0;
 	for (int i = 0; i < L; i++) {
 		if (j > i) {
-			ulong temp = source[base+i];
+			elem temp = source[base+i];
 			source[base+i] = source[base+j];
 			source[base+j] = temp;
       	}
 		int mask = L >> 1;
 		while ((j & mask) != 0) {
-			j &= !mask;
+			j &= ~mask;
 			mask >>= 1;
 		}
 		j |= mask;
@@ -23,39 +308,45 @@ void radix2_bitreverse (
 
 
 void radix2_dft (
-	__global ulong* source,
-	__global const ulong* roots2,
+	__global elem* source,
+	TWIDDLE const elem* twiddles2,
 	const ulong base,
   	const ulong L,
-	const ulong L_inv,
   	const ulong L_bit_num,
-  	const ulong P)
+  	const elem P
+  	TRACE_ARGS)
 {
-	for (int s = 0; s < L_bit_num + 1; s++) {
-		int m = (int) pown((float) 2, s);
+	for (int s = 1; s < L_bit_num + 1; s++) {
+		int m = 1 << s;
+		TWIDDLE const elem* tw = twiddles2 + (m/2 - 1);
 		int i = 0;
 
 		while (i < L) {
 			int j = 0;
 			while (j < m/2) {
-				ulong t = roots2[base+j*(L/m)] * source[base+i+j+m/2] % P;
-				ulong u = source[base+i+j] % P;
-				source[i+j] = (u + t) % P;
-				if (u <= t) {
-					source[base+i+j+m/2] = (P + u - t) % P;
-				} else {
-					source[base+i+j+m/2] = (u - t) % P;
-				}
-				printf("base # %d, changing %u, (%u, %u, %u, %u): %llu, %llu\n", 
-						get_global_id(0), i+j+m/2, base, i, j, m, u, t);
+				elem t = mul_mod(tw[j], source[base+i+j+m/2], P);
+				elem u = source[base+i+j];
+				source[base+i+j] = add_mod(u, t, P);
+				source[base+i+j+m/2] = sub_mod(u, t, P);
+				TRACE(base/L, m, i+j, source[base+i+j]);
+				TRACE(base/L, m, i+j+m/2, source[base+i+j+m/2]);
 				j ++;
 			}
 			i += m;
 		}
 	}
+}
 
+// F^-1(Y) = nX, multiply by n^-1
+void scale (
+	__global elem* source,
+	const ulong base,
+	const ulong L,
+	const elem L_inv,
+	const elem P)
+{
 	for (int i = 0; i < L; i ++){
-		source[base+i] = source[base+i] * L_inv % P;
+		source[base+i] = mul_mod(source[base+i], L_inv, P);
 	}
 }
 
@@ -63,44 +354,51 @@ void radix2_dft (
 //typedef
 //c preprocessor
 
-__kernel void ntt_inverse2 (
-	__global ulong* source,
-	__global ulong* roots2,		//inversed
+__kernel void ntt_transform2 (
+	__global elem* source,
+	TWIDDLE const elem* twiddles2,
   	const ulong L,
-  	const ulong L_inv,
   	const ulong L_bit_num,
-  	const ulong P)
+  	const elem P
+  	TRACE_ARGS)
 {
 	ulong const base = get_global_id(0) * L;
-	printf("%d", get_local_id(0));
-
-	for (int i = 0; i < L; i++) {
-		printf("%d, source %d roots2 %d\n", i, source[i], roots2[i]);
-	}
 
 	radix2_bitreverse(source, base, L);
-	radix2_dft(source, roots2, base, L, L_inv, L_bit_num, P);
+	radix2_dft(source, twiddles2, base, L, L_bit_num, P TRACE_PASS);
+}
 
+__kernel void ntt_inverse2 (
+	__global elem* source,
+	TWIDDLE const elem* twiddles2,		//inversed
+  	const ulong L,
+  	const elem L_inv,
+  	const ulong L_bit_num,
+  	const elem P
+  	TRACE_ARGS)
+{
+	ulong const base = get_global_id(0) * L;
+
+	radix2_bitreverse(source, base, L);
+	radix2_dft(source, twiddles2, base, L, L_bit_num, P TRACE_PASS);
+	scale(source, base, L, L_inv, P);
 }
 "#;
 
 
-pub static NTT_TRANSFORM3_PART1: &str = r#"
+pub static NTT_RADIX3: &str = r#"
 
 void radix3_bitreverse (
-	__global ulong* source, 
+	__global elem* source, 
 	const ulong base,
   	const ulong L)
 {
-	int L_trigits_num ="#;
-
-pub static NTT_TRANSFORM3_PART2: &str = r#";
-	__private int trigits["#;
-pub static NTT_TRANSFORM3_PART3: &str = r#"] = {0};
+	int L_trigits_num = TRIGITS;
+	__private int trigits[TRIGITS] = {0};
 	int t = 0;
 	for (int i = 0; i < L; i++) {
 		if (t > i) {
-			ulong temp = source[base+i];
+			elem temp = source[base+i];
 			source[base+i] = source[base+t];
 			source[base+t] = temp;
       	}
@@ -119,29 +417,33 @@ pub static NTT_TRANSFORM3_PART3: &str = r#"] = {0};
 
 
 void radix3_dft (
-	__global ulong* source,
-	__global ulong* roots3,
+	__global elem* source,
+	TWIDDLE const elem* twiddles3,
 	const ulong base,
 	const ulong L,
-	const ulong P)
+	const elem P
+	TRACE_ARGS)
 {
-	ulong w = roots3[L/3];
-	ulong w_sqr = roots3[L/3*2];
+	elem w = twiddles3[L-1];
+	elem w_sqr = twiddles3[L];
 
 	int i = 1;
 	while (i < L) {
 		int jump = 3 * i;
-		int stride = L/jump;
+		TWIDDLE const elem* tw = twiddles3 + (i - 1);
 		for (int j = 0; j < i; j++) {
 			int pair = j;
 			while (pair < L) {
-				ulong x = source[base+pair];
-				ulong y = source[base+pair+1] * roots3[j*stride] % P;
-				ulong z = source[base+pair+2*i] * roots3[2*j*stride] % P;
+				elem x = source[base+pair];
+				elem y = mul_mod(source[base+pair+i], tw[2*j], P);
+				elem z = mul_mod(source[base+pair+2*i], tw[2*j+1], P);
 
-				source[base+pair] 	  = (x + y + z) % P;
-				source[base+pair+i]   = (x % P + w * y % P + w_sqr * z % P) % P;
-                source[base+pair+2*i] = (x % P + w_sqr * y % P + w * z % P) % P;
+				source[base+pair] 	  = add_mod(add_mod(x, y, P), z, P);
+				source[base+pair+i]   = add_mod(add_mod(x, mul_mod(w, y, P), P), mul_mod(w_sqr, z, P), P);
+				source[base+pair+2*i] = add_mod(add_mod(x, mul_mod(w_sqr, y, P), P), mul_mod(w, z, P), P);
+				TRACE(base/L, jump, pair, source[base+pair]);
+				TRACE(base/L, jump, pair+i, source[base+pair+i]);
+				TRACE(base/L, jump, pair+2*i, source[base+pair+2*i]);
 
                 pair += jump;
   			}
@@ -150,18 +452,387 @@ void radix3_dft (
 	}
 }
 
+void scale3 (
+	__global elem* source,
+	const ulong base,
+	const ulong L,
+	const elem L_inv,
+	const elem P)
+{
+	for (int i = 0; i < L; i ++){
+		source[base+i] = mul_mod(source[base+i], L_inv, P);
+	}
+}
+
 __kernel void ntt_transform3 (
-	__global ulong* source, 
-	__global ulong* roots3,
+	__global elem* source, 
+	TWIDDLE const elem* twiddles3,
   	const ulong L,
-  	const ulong P)
+  	const elem P
+  	TRACE_ARGS)
 {
 	ulong const base = get_global_id(0) * L;
-	// printf("%d", get_local_id(0));
 
 	radix3_bitreverse(source, base, L);
-	radix3_dft(source, roots3, base, L, P);
+	radix3_dft(source, twiddles3, base, L, P TRACE_PASS);
+}
+
+__kernel void ntt_inverse3 (
+	__global elem* source, 
+	TWIDDLE const elem* twiddles3,		//inversed
+  	const ulong L,
+  	const elem L_inv,
+  	const elem P
+  	TRACE_ARGS)
+{
+	ulong const base = get_global_id(0) * L;
+
+	radix3_bitreverse(source, base, L);
+	radix3_dft(source, twiddles3, base, L, P TRACE_PASS);
+	scale3(source, base, L, L_inv, P);
+}
+"#;
+
+pub static PSS_RECONSTRUCT: &str = r#"
+
+// one work item per block
+// shares [M][B] party major, coeffs [L][M], secrets [B][L]
+// coeffs in Montgomery form take plain shares to plain secrets
+__kernel void pss_reconstruct (
+	__global const elem* shares,
+	__global const elem* coeffs,
+	__global elem* secrets,
+	const ulong M,
+	const ulong B,
+	const ulong L,
+	const elem P)
+{
+	ulong const block = get_global_id(0);
+
+	for (ulong k = 0; k < L; k++) {
+		elem acc = elem_from(0);
+		for (ulong i = 0; i < M; i++) {
+			acc = add_mod(acc, mul_mod(coeffs[k*M+i], shares[i*B+block], P), P);
+		}
+		secrets[block*L+k] = acc;
+	}
+}
+"#;
+
+pub static PSS_PIPELINE: &str = r#"
+
+// one work item per block
+// source [B][L2] -> dest [B][L3], zeros at the high coefficients
+__kernel void zero_extend (
+	__global const elem* source,
+	__global elem* dest,
+	const ulong L2,
+	const ulong L3)
+{
+	ulong const block = get_global_id(0);
+
+	for (ulong i = 0; i < L2; i++) {
+		dest[block*L3+i] = source[block*L2+i];
+	}
+	for (ulong i = L2; i < L3; i++) {
+		dest[block*L3+i] = elem_from(0);
+	}
+}
+"#;
+
+pub static PSS_TRANSPOSE: &str = r#"
+
+// one work item per (block, row)
+// polys [B][L3] block major -> shares [R][B] party major, row r is point first+r
+// the shares leave the Montgomery form here
+// the host keeps first+row < L3 (num_shares < L3), rows past it are skipped
+__kernel void transpose_shares (
+	__global const elem* polys,
+	__global elem* shares,
+	const ulong B,
+	const ulong L3,
+	const ulong first,
+	const elem P)
+{
+	ulong const block = get_global_id(0);
+	ulong const row = get_global_id(1);
+
+	if (first+row >= L3) {
+		return;
+	}
+	shares[row*B+block] = from_mont(polys[block*L3+first+row], P);
+}
+"#;
+
+pub static PSS_RANDOM: &str = r#"
+
+// Philox4x32-10, Salmon et al. "Parallel random numbers: as easy as 1, 2, 3"
+#define PHILOX_M0 0xD2511F53u
+#define PHILOX_M1 0xCD9E8D57u
+#define PHILOX_W0 0x9E3779B9u
+#define PHILOX_W1 0xBB67AE85u
+
+uint4 philox4x32 (uint4 ctr, uint2 key)
+{
+	for (int r = 0; r < 10; r++) {
+		uint hi0 = mul_hi(PHILOX_M0, ctr.x);
+		uint lo0 = PHILOX_M0 * ctr.x;
+		uint hi1 = mul_hi(PHILOX_M1, ctr.z);
+		uint lo1 = PHILOX_M1 * ctr.z;
+		ctr = (uint4)(hi1 ^ ctr.y ^ key.x, lo1, hi0 ^ ctr.w ^ key.y, lo0);
+		key.x += PHILOX_W0;
+		key.y += PHILOX_W1;
+	}
+	return ctr;
+}
+
+/* Uniform in [0, P): every draw gives two ulongs,
+   anything at or above zone = P * floor(2^64 / P) is rejected
+   the counter is (block, slot, attempt, call), unique per draw of a context
+   together with the high half of the call number in the key, see fill_blocks
+   WIDE: one draw is a ulong2 masked with WIDE_MASK_LO/HI
+   to the bit length of P, rejected at or above P, zone is unused
+*/
+#ifdef WIDE
+
+elem philox_mod (ulong block, ulong slot, uint call, uint2 key, ulong zone, elem P)
+{
+	for (uint attempt = 0; ; attempt++) {
+		uint4 ctr = (uint4)((uint) block, (uint) slot, attempt, call);
+		uint4 r = philox4x32(ctr, key);
+		elem x = (ulong2)((((ulong) r.x << 32) | r.y) & WIDE_MASK_LO, (((ulong) r.z << 32) | r.w) & WIDE_MASK_HI);
+		if (!ge2(x, P)) {
+			return x;
+		}
+	}
+}
+
+#else
+
+elem philox_mod (ulong block, ulong slot, uint call, uint2 key, ulong zone, elem P)
+{
+	for (uint attempt = 0; ; attempt++) {
+		uint4 ctr = (uint4)((uint) block, (uint) slot, attempt, call);
+		uint4 r = philox4x32(ctr, key);
+		ulong x = ((ulong) r.x << 32) | r.y;
+		if (x < zone) {
+			return (elem) (x % P);
+		}
+		x = ((ulong) r.z << 32) | r.w;
+		if (x < zone) {
+			return (elem) (x % P);
+		}
+	}
+}
+
+#endif
+
+// one work item per block
+// secrets [B][L] -> blocks [B][L2], slots L..L2 random
+// the secrets enter the Montgomery form here, a uniform draw already is one
+// call numbers the share calls of a context: low half in the counter, high half in the key,
+// so no two calls draw the same stream (B < 2^32)
+__kernel void fill_blocks (
+	__global const elem* secrets,
+	__global elem* blocks,
+	const ulong L,
+	const ulong L2,
+	const uint key0,
+	const uint key1,
+	const ulong call,
+	const ulong zone,
+	const elem P)
+{
+	ulong const block = get_global_id(0);
+	uint2 const key = (uint2)(key0, key1 ^ (uint) (call >> 32));
 
+	for (ulong i = 0; i < L; i++) {
+		blocks[block*L2+i] = to_mont(elem_reduce(secrets[block*L+i], P), P);
+	}
+	for (ulong i = L; i < L2; i++) {
+		blocks[block*L2+i] = philox_mod(block, i, (uint) call, key, zone, P);
+	}
 }
 "#;
 
+pub static NTT_GROUP: &str = r#"
+
+/* One work group per poly: the poly is loaded into __local memory
+   in digit reversed order, every stage spreads its butterflies
+   over the work items and ends with a barrier
+*/
+
+void radix2_group_dft (
+	__local elem* buf,
+	TWIDDLE const elem* twiddles2,
+	const ulong L,
+	const elem P
+	TRACE_ARGS)
+{
+	ulong const t = get_local_id(0);
+	ulong const S = get_local_size(0);
+
+	for (ulong m = 2; m <= L; m <<= 1) {
+		ulong const half = m >> 1;
+		TWIDDLE const elem* tw = twiddles2 + (half - 1);
+		for (ulong k = t; k < L/2; k += S) {
+			ulong const j = k % half;
+			ulong const i = (k / half) * m;
+			elem w = tw[j];
+			elem u = buf[i+j];
+			elem v = mul_mod(w, buf[i+j+half], P);
+			buf[i+j] = add_mod(u, v, P);
+			buf[i+j+half] = sub_mod(u, v, P);
+			TRACE(get_group_id(0), m, i+j, buf[i+j]);
+			TRACE(get_group_id(0), m, i+j+half, buf[i+j+half]);
+		}
+		barrier(CLK_LOCAL_MEM_FENCE);
+	}
+}
+
+void radix2_group_load (
+	__global const elem* source,
+	__local elem* buf,
+	const ulong base,
+	const ulong L,
+	const ulong L_bit_num)
+{
+	for (ulong i = get_local_id(0); i < L; i += get_local_size(0)) {
+		ulong r = 0;
+		for (ulong b = 0; b < L_bit_num; b++) {
+			r |= ((i >> b) & 1) << (L_bit_num - 1 - b);
+		}
+		buf[r] = source[base+i];
+	}
+	barrier(CLK_LOCAL_MEM_FENCE);
+}
+
+void radix3_group_dft (
+	__local elem* buf,
+	TWIDDLE const elem* twiddles3,
+	const ulong L,
+	const elem P
+	TRACE_ARGS)
+{
+	ulong const t = get_local_id(0);
+	ulong const S = get_local_size(0);
+	elem const w = twiddles3[L-1];
+	elem const w_sqr = twiddles3[L];
+
+	for (ulong i = 1; i < L; i *= 3) {
+		ulong const jump = 3 * i;
+		TWIDDLE const elem* tw = twiddles3 + (i - 1);
+		for (ulong k = t; k < L/3; k += S) {
+			ulong const j = k % i;
+			ulong const pair = (k / i) * jump + j;
+			elem x = buf[pair];
+			elem y = mul_mod(buf[pair+i], tw[2*j], P);
+			elem z = mul_mod(buf[pair+2*i], tw[2*j+1], P);
+
+			buf[pair]     = add_mod(add_mod(x, y, P), z, P);
+			buf[pair+i]   = add_mod(add_mod(x, mul_mod(w, y, P), P), mul_mod(w_sqr, z, P), P);
+			buf[pair+2*i] = add_mod(add_mod(x, mul_mod(w_sqr, y, P), P), mul_mod(w, z, P), P);
+			TRACE(get_group_id(0), jump, pair, buf[pair]);
+			TRACE(get_group_id(0), jump, pair+i, buf[pair+i]);
+			TRACE(get_group_id(0), jump, pair+2*i, buf[pair+2*i]);
+		}
+		barrier(CLK_LOCAL_MEM_FENCE);
+	}
+}
+
+void radix3_group_load (
+	__global const elem* source,
+	__local elem* buf,
+	const ulong base,
+	const ulong L)
+{
+	for (ulong i = get_local_id(0); i < L; i += get_local_size(0)) {
+		ulong r = 0;
+		ulong x = i;
+		for (ulong n = 1; n < L; n *= 3) {
+			r = r * 3 + x % 3;
+			x /= 3;
+		}
+		buf[r] = source[base+i];
+	}
+	barrier(CLK_LOCAL_MEM_FENCE);
+}
+
+// scale by L_inv (1, in device form, for the forward transforms) on the way out
+void group_store (
+	__local const elem* buf,
+	__global elem* source,
+	const ulong base,
+	const ulong L,
+	const elem L_inv,
+	const elem P)
+{
+	for (ulong i = get_local_id(0); i < L; i += get_local_size(0)) {
+		source[base+i] = mul_mod(buf[i], L_inv, P);
+	}
+}
+
+__kernel void ntt_transform2_group (
+	__global elem* source,
+	TWIDDLE const elem* twiddles2,
+	const ulong L,
+	const ulong L_bit_num,
+	const elem P,
+	__local elem* buf
+	TRACE_ARGS)
+{
+	ulong const base = get_group_id(0) * L;
+
+	radix2_group_load(source, buf, base, L, L_bit_num);
+	radix2_group_dft(buf, twiddles2, L, P TRACE_PASS);
+	group_store(buf, source, base, L, to_mont(elem_from(1), P), P);
+}
+
+__kernel void ntt_inverse2_group (
+	__global elem* source,
+	TWIDDLE const elem* twiddles2,		//inversed
+	const ulong L,
+	const elem L_inv,
+	const ulong L_bit_num,
+	const elem P,
+	__local elem* buf
+	TRACE_ARGS)
+{
+	ulong const base = get_group_id(0) * L;
+
+	radix2_group_load(source, buf, base, L, L_bit_num);
+	radix2_group_dft(buf, twiddles2, L, P TRACE_PASS);
+	group_store(buf, source, base, L, L_inv, P);
+}
+
+__kernel void ntt_transform3_group (
+	__global elem* source,
+	TWIDDLE const elem* twiddles3,
+	const ulong L,
+	const elem P,
+	__local elem* buf
+	TRACE_ARGS)
+{
+	ulong const base = get_group_id(0) * L;
+
+	radix3_group_load(source, buf, base, L);
+	radix3_group_dft(buf, twiddles3, L, P TRACE_PASS);
+	group_store(buf, source, base, L, to_mont(elem_from(1), P), P);
+}
+
+__kernel void ntt_inverse3_group (
+	__global elem* source,
+	TWIDDLE const elem* twiddles3,		//inversed
+	const ulong L,
+	const elem L_inv,
+	const elem P,
+	__local elem* buf
+	TRACE_ARGS)
+{
+	ulong const base = get_group_id(0) * L;
+
+	radix3_group_load(source, buf, base, L);
+	radix3_group_dft(buf, twiddles3, L, P TRACE_PASS);
+	group_store(buf, source, base, L, L_inv, P);
+}
+"#;
@@ -1,31 +1,152 @@
 #![allow(non_snake_case)]
 
+#[cfg(feature = "opencl")]
 use std::convert::*;
+#[cfg(feature = "opencl")]
+use std::collections::HashMap;
+#[cfg(feature = "opencl")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "opencl")]
 use std::fmt::Debug;
-use time;
 
+#[cfg(feature = "opencl")]
 use rand::{thread_rng, Rng};
+#[cfg(feature = "opencl")]
 use rand::distributions::uniform::SampleUniform;
+#[cfg(feature = "opencl")]
 use num::traits::Unsigned;
 
-use ocl::{Platform, Device, ProQue, Buffer, SpatialDims};
+#[cfg(feature = "opencl")]
+use ocl::{ProQue, Buffer, Queue, Event};
+#[cfg(feature = "opencl")]
 use ocl::core;
-use ocl::core::{DeviceInfo};
+#[cfg(feature = "opencl")]
+use ocl::enums::{KernelWorkGroupInfo, KernelWorkGroupInfoResult};
+#[cfg(feature = "opencl")]
 use ocl::traits::OclPrm;
 
+#[cfg(feature = "opencl")]
+use pss_compare::ReconstructionPlan;
+#[cfg(feature = "opencl")]
+use pss_compare::twiddle::{stage_table2, stage_table3};
+pub use pss_compare::SharingBackend;
+
+/* Everything but util and fallback needs the opencl feature (on by default),
+   without it PssContext always shares on the CPU
+*/
 pub mod util;
+pub mod fallback;
+#[cfg(feature = "opencl")]
+pub mod device;
+#[cfg(feature = "opencl")]
+pub mod pool;
+#[cfg(feature = "opencl")]
+pub mod stream;
+#[cfg(feature = "opencl")]
+pub mod multi;
+#[cfg(feature = "opencl")]
+pub mod program;
+#[cfg(feature = "opencl")]
+pub mod trace;
+#[cfg(feature = "opencl")]
+pub mod elem;
+#[cfg(feature = "opencl")]
+pub mod error;
+#[cfg(feature = "opencl")]
+pub mod diagnostics;
+#[cfg(feature = "opencl")]
 mod kernel;
 use util::*;
-use kernel::*;
+pub use fallback::{PssContext, PssElem};
+#[cfg(feature = "opencl")]
+use pool::BufferPool;
+#[cfg(feature = "opencl")]
+use program::{KernelParams, build_pro_que};
+#[cfg(feature = "opencl")]
+pub use program::{BuildProfile, Reduction};
+#[cfg(feature = "opencl")]
+use trace::TraceBuffers;
+#[cfg(feature = "opencl")]
+pub use device::{DeviceCandidate, DeviceSelector, list_devices};
+#[cfg(feature = "opencl")]
+pub use stream::ShareChunk;
+#[cfg(feature = "opencl")]
+pub use multi::MultiOclContext;
+#[cfg(feature = "opencl")]
+pub use elem::OclElem;
+#[cfg(feature = "opencl")]
+pub use error::OclError;
+#[cfg(feature = "opencl")]
+pub use diagnostics::{Diagnostics, diagnose};
+
+/* Which evaluation points share returns
+   Distributed: rows 1..=num_shares, one per party
+   All:         every row 0..degree3, row 0 is the evaluation at 1
+*/
+#[cfg(feature = "opencl")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShareRows {
+    Distributed,
+    All,
+}
+
+#[cfg(feature = "opencl")]
+impl ShareRows {
+    // (first row, number of rows)
+    fn range(&self, num_shares: usize, degree3: usize) -> (usize, usize) {
+        match self {
+            ShareRows::Distributed => (1, num_shares),
+            ShareRows::All => (0, degree3),
+        }
+    }
+}
+
+/* How the NTT kernels map onto the device
+   PerWorkItem: one work item transforms a whole poly in global memory,
+                for huge batches that fill the device anyway
+   WorkGroup:   one work group per poly, the stages run in __local memory
+   Auto:        WorkGroup while a poly fits in local memory,
+                the batch is too small to fill the device
+                and the kernel takes groups of AUTO_MIN_GROUP_SIZE items
+*/
+#[cfg(feature = "opencl")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KernelMode {
+    PerWorkItem,
+    WorkGroup,
+    Auto,
+}
+
+// batches of at least compute_units * this many polys go per work item in Auto
+#[cfg(feature = "opencl")]
+const AUTO_POLYS_PER_UNIT: u64 = 256;
+
+// narrower groups leave most of a compute unit idle, Auto then goes per work item
+#[cfg(feature = "opencl")]
+const AUTO_MIN_GROUP_SIZE: usize = 64;
+
+// twiddle tables of pss_compare::twiddle, resident for the lifetime of the context
+#[cfg(feature = "opencl")]
+struct Twiddles<L: OclPrm> {
+    forward2: Buffer<L>,
+    inverse2: Buffer<L>,
+    forward3: Buffer<L>,
+    inverse3: Buffer<L>,
+}
 
-pub struct OclContext {
+/* Device buffers hold T::LIMBS T::Limb per element, see OclElem:
+   u32 for primes below 2^32, u64 below 2^62, u128 as limb pairs below 2^127
+*/
+#[cfg(feature = "opencl")]
+pub struct OclContext<T: OclElem = u64> {
 
     pub compute_units: u64,
+    device: DeviceCandidate,
     pro_que: ocl::ProQue,
 
-    prime: u64,
-    roots2: Vec<u64>,
-    roots3: Vec<u64>,
+    prime: T,
+    roots2: Vec<T>,
+    roots3: Vec<T>,
     degree2: usize,
     degree3: usize,
 
@@ -33,298 +154,656 @@ pub struct OclContext {
     L: usize,
     N: usize,
 
+    kernel_mode: KernelMode,
+    params: KernelParams,
+
+    // built once in with_device, arguments rebound per call
+    kernels: HashMap<String, ocl::Kernel>,
+    // CL_KERNEL_WORK_GROUP_SIZE of the _group kernels
+    group_sizes: HashMap<String, usize>,
+    twiddles: Twiddles<T::Limb>,
+    pool: BufferPool<T::Limb>,
+    // debug builds only
+    trace: Option<TraceBuffers>,
+
+    // device copies of reconstruction coefficients keyed by share points
+    plans: HashMap<Vec<T>, Buffer<T::Limb>>,
+
+    // Philox key of the padding randomness and the number of share calls so far
+    philox_key: [u32; 2],
+    calls: AtomicU64,
 }
 
 
 
-impl OclContext{
+#[cfg(feature = "opencl")]
+impl<T: OclElem> OclContext<T> {
 
-    /* Use u64 because any T implements Into<u64>
+    /* Device from PSS_OCL_DEVICE, or the one with the most compute units
     */
+    pub fn new(prime: T, root2: T, root3: T, 
+               degree2: usize, degree3: usize, total_len: usize, packing_len: usize, num_shares: usize) -> Result<OclContext<T>, OclError> {
+        OclContext::with_device(&DeviceSelector::Env, prime, root2, root3,
+            degree2, degree3, total_len, packing_len, num_shares)
+    }
 
-    pub fn new(prime: u64, root2: u64, root3: u64, 
-               degree2: usize, degree3: usize, total_len: usize, packing_len: usize, num_shares: usize) -> Option<OclContext> {
-        
-        println!("{:?} {:?} {:?} {:?} {:?}", degree2, degree3, total_len, packing_len, num_shares);
-        // wich Device should we choose?
-        // the one with the most Compute units!
-        let mut compute_units = 0;
-        let mut ocl_device = None;
-        let platforms = Platform::list();
-        for p_idx in 0..platforms.len() {
-            let platform = &platforms[p_idx];
-            let devices = Device::list_all(platform).unwrap();
-            for d_idx in 0..devices.len() {
-                let device = devices[d_idx];
-                let deviceinfodest = core::get_device_info(
-                    &device, 
-                    DeviceInfo::MaxComputeUnits
-                );
-                let units = deviceinfodest
-                            .unwrap()
-                            .to_string()
-                            .parse()
-                            .unwrap();
-                if units > compute_units {
-                    ocl_device = Some(device);
-                    compute_units = units;
-                }
-                println!("{:?} {:?}", core::get_device_info(&device, DeviceInfo::Name),
-                    core::get_device_info(&device, DeviceInfo::MaxComputeUnits));
-            }
+    // build profile from PSS_OCL_PROFILE, release if unset
+    pub fn with_device(selector: &DeviceSelector, prime: T, root2: T, root3: T, 
+               degree2: usize, degree3: usize, total_len: usize, packing_len: usize, num_shares: usize) -> Result<OclContext<T>, OclError> {
+        OclContext::with_profile(selector, BuildProfile::from_env(), prime, root2, root3,
+            degree2, degree3, total_len, packing_len, num_shares)
+    }
 
-        }
-        // something went wrong, opencl not installed
-        if compute_units == 0 {
-            return None
-        }
+    // reduction from PSS_OCL_REDUCTION, plain if unset
+    pub fn with_profile(selector: &DeviceSelector, profile: BuildProfile, prime: T, root2: T, root3: T, 
+               degree2: usize, degree3: usize, total_len: usize, packing_len: usize, num_shares: usize) -> Result<OclContext<T>, OclError> {
+        OclContext::with_reduction(selector, profile, Reduction::from_env(), prime, root2, root3,
+            degree2, degree3, total_len, packing_len, num_shares)
+    }
 
-        let L3_trigits_len = trigits_len(degree3);
-        let kernel_code = format!("{}{}{}{}{}{}", 
-            NTT_INVERSE2,
-            NTT_TRANSFORM3_PART1,
-            L3_trigits_len,
-            NTT_TRANSFORM3_PART2,
-            L3_trigits_len,
-            NTT_TRANSFORM3_PART3);
-
-        let que = ProQue::builder()
-                  .device(ocl_device.unwrap())
-                  .src(kernel_code)
-                  .build().expect("Build ProQue");
-
-        
+    /* 128 bit elements always use Reduction::Montgomery, 32 bit ones Reduction::Plain,
+    tracing (BuildProfile::Debug) needs u64 elements
+    OclError::Parameters for a prime at or above T::MAX_PRIME, whatever the hardware,
+    OclError::NoDevice without OpenCL or a matching device,
+    OclError::Unsupported if diagnose finds the device cannot run the kernels
+    */
+    pub fn with_reduction(selector: &DeviceSelector, profile: BuildProfile, reduction: Reduction, prime: T, root2: T, root3: T, 
+               degree2: usize, degree3: usize, total_len: usize, packing_len: usize, num_shares: usize) -> Result<OclContext<T>, OclError> {
         assert!(total_len % packing_len == 0);
         assert!(packing_len <= degree2);
         assert!(degree2 <= num_shares);
-        assert!(num_shares <= degree3);
-
-        let P = prime;
-        let mut inv_roots2: Vec<u64> = Vec::new();
-        let inv_root2 = root2.modpow(P - 2u64, P);
-        for i in 0..degree2 {
-            let wi = inv_root2.modpow(i as u64, P);
-            inv_roots2.push(wi);    
+        // row num_shares of a block is point num_shares, it must exist (as in pss_compare)
+        assert!(num_shares < degree3);
+        assert!(T::BITS == 64 || profile == BuildProfile::Release, "tracing needs u64 elements");
+
+        let P = prime.to_u128();
+        if P >= T::MAX_PRIME {
+            return Err(OclError::Parameters {
+                reason: format!("prime {} too large for {}-bit elements, the kernels take primes below 2^{}",
+                    P, T::BITS, 128 - T::MAX_PRIME.leading_zeros() - 1),
+            });
         }
-        //Constant memory __constant
 
-        let mut roots3: Vec<u64> = Vec::new();
-        for i in 0..degree3 {
-            let wi = root3.modpow(i as u64, P);
-            roots3.push(wi);
+        let candidates = list_devices();
+        let device = match selector.select(&candidates) {
+            Some(device) => device.clone(),
+            None => return Err(OclError::NoDevice {
+                selector: format!("{:?}", selector),
+                available: candidates.iter().map(|c| c.to_string()).collect(),
+            }),
+        };
+        let diagnostics = diagnose::<T>(&device, degree2, degree3);
+        if !diagnostics.is_supported() {
+            return Err(OclError::Unsupported { diagnostics: diagnostics });
         }
 
-        Some(OclContext {
-            compute_units: compute_units,
+        let wide = T::LIMBS > 1;
+        let reduction = match T::BITS {
+            32 => Reduction::Plain,
+            128 => Reduction::Montgomery,
+            _ => reduction,
+        };
+
+        // powers in u128, correct for every prime below 2^127
+        let powers = |root: u128, n: usize| -> Vec<u128> {
+            let mut ret = Vec::with_capacity(n);
+            let mut x = 1u128;
+            for _ in 0..n {
+                ret.push(x);
+                x = mul_mod128(x, root, P);
+            }
+            ret
+        };
+        let (root2, root3) = (root2.to_u128(), root3.to_u128());
+        let roots2 = powers(root2, degree2);
+        let inv_roots2 = powers(pow_mod128(root2, P - 2, P), degree2);
+        let roots3 = powers(root3, degree3);
+        let inv_roots3 = powers(pow_mod128(root3, P - 2, P), degree3);
+
+        let mut tables = vec![stage_table2(&roots2), stage_table2(&inv_roots2),
+                              stage_table3(&roots3), stage_table3(&inv_roots3)];
+        if reduction == Reduction::Montgomery {
+            for table in tables.iter_mut() {
+                for w in table.iter_mut() {
+                    *w = if wide { to_montgomery128(*w, P) } else { to_montgomery(*w as u64, P as u64) as u128 };
+                }
+            }
+        }
+        let tables: Vec<Vec<T>> = tables.into_iter()
+            .map(|t| t.into_iter().map(T::from_u128).collect())
+            .collect();
+
+        // a kernel binds one table at a time, the largest has degree3 + 1 entries
+        let twiddle_constant = (tables[2].len().max(tables[0].len()) * T::bytes()) as u64 <= device.max_constant_buffer_size;
+        let params = KernelParams::new(degree3, P, T::BITS, twiddle_constant, profile, reduction);
+        let que = build_pro_que(&device, &params)?;
+
+        let ocl_err = |e| OclError::ocl(&device, e);
+        let twiddles = Twiddles {
+            forward2: upload(&que, &T::to_limbs(&tables[0])).map_err(ocl_err)?,
+            inverse2: upload(&que, &T::to_limbs(&tables[1])).map_err(ocl_err)?,
+            forward3: upload(&que, &T::to_limbs(&tables[2])).map_err(ocl_err)?,
+            inverse3: upload(&que, &T::to_limbs(&tables[3])).map_err(ocl_err)?,
+        };
+        let debug = profile == BuildProfile::Debug;
+        let kernels = build_kernels::<T>(&que, degree2, degree3, debug).map_err(ocl_err)?;
+        let group_sizes = group_sizes(&que, &kernels, device.max_work_group_size as usize).map_err(ocl_err)?;
+        let pool = BufferPool::new(que.queue().clone());
+        let trace = if debug { Some(TraceBuffers::new(que.queue())) } else { None };
+        let mut rng = thread_rng();
+
+        Ok(OclContext {
+            compute_units: device.compute_units,
+            device: device,
             pro_que : que,
 
             prime: prime,
-            roots2: inv_roots2,
-            roots3: roots3,
+            roots2: roots2.into_iter().map(T::from_u128).collect(),
+            roots3: roots3.into_iter().map(T::from_u128).collect(),
             degree2: degree2,
             degree3: degree3,
 
             V: total_len,
             L: packing_len,
             N: num_shares,
+
+            kernel_mode: KernelMode::Auto,
+            params: params,
+
+            kernels: kernels,
+            group_sizes: group_sizes,
+            twiddles: twiddles,
+            pool: pool,
+            trace: trace,
+
+            plans: HashMap::new(),
+
+            philox_key: [rng.gen(), rng.gen()],
+            calls: AtomicU64::new(0),
         })
     }
 
-    pub fn share(&mut self, secrets: &[u64]) -> Vec<Vec<u64>> {   
+    pub fn device(&self) -> &DeviceCandidate {
+        &self.device
+    }
+
+    pub fn diagnostics(&self) -> Diagnostics {
+        diagnose::<T>(&self.device, self.degree2, self.degree3)
+    }
+
+    pub fn set_kernel_mode(&mut self, mode: KernelMode) {
+        self.kernel_mode = mode;
+    }
+
+    pub fn kernel_mode(&self) -> KernelMode {
+        self.kernel_mode
+    }
+
+    pub fn kernel_params(&self) -> &KernelParams {
+        &self.params
+    }
+
+    pub fn reduction(&self) -> Reduction {
+        self.params.reduction
+    }
+
+    /* A constant as the kernels multiply with it, Montgomery form if so built,
+    the product with a value on the device then stays in that value's form
+    */
+    fn device_constant(&self, x: u128) -> T {
+        let P = self.prime.to_u128();
+        T::from_u128(match self.params.reduction {
+            Reduction::Plain => x,
+            Reduction::Montgomery if T::LIMBS > 1 => to_montgomery128(x, P),
+            Reduction::Montgomery => to_montgomery(x as u64, P as u64) as u128,
+        })
+    }
+
+    // x^-1 mod P
+    fn inverse(&self, x: u128) -> u128 {
+        let P = self.prime.to_u128();
+        pow_mod128(x, P - 2, P)
+    }
+
+    // drop the pooled data buffers, they are reallocated on the next call
+    pub fn release_buffers(&mut self) {
+        self.pool.clear();
+    }
+
+    pub fn share(&mut self, secrets: &[T]) -> Vec<Vec<T>> {
+        self.share_rows(secrets, ShareRows::Distributed)
+    }
+
+    pub fn share_rows(&mut self, secrets: &[T], rows: ShareRows) -> Vec<Vec<T>> {   
         /* Input Format
            [x0, ..., xv]
         */
         assert!(secrets.len() == self.V);
+        self.share_blocks(secrets, rows)
+    }
+
+    // any whole number of blocks, [R][secrets.len() / L]
+    fn share_blocks(&mut self, secrets: &[T], rows: ShareRows) -> Vec<Vec<T>> {
+        assert!(secrets.len() % self.L == 0);
+        let B = secrets.len() / self.L;
+        let (_, R) = rows.range(self.N, self.degree3);
+
+        let buffers = ShareBuffers::take(&mut self.pool, B, self.L, self.degree2, self.degree3, R, T::LIMBS);
+        let queue = self.pro_que.queue().clone();
+        let input = T::to_limbs(secrets);
+
+        buffers.input.write(&input[..]).queue(&queue).len(input.len()).enq().unwrap();
+        unsafe {
+            self.enqueue_share(&queue, &buffers, B, rows, None);
+        }
+        queue.finish().unwrap();
+
+        /* Output Format
+           [[s00, ..., s0b], ..., [sr0, ..., srb]]     //shares per row
+        */
+        let mut res = vec![T::Limb::default(); R * B * T::LIMBS];
+        buffers.shares.read(&mut res).queue(&queue).len(R * B * T::LIMBS).enq().unwrap();
+
+        let ret: Vec<Vec<T>> = res.chunks(B * T::LIMBS).map(T::from_limbs).collect();
+        ret
+    }
+
+    /* Enqueue the share pipeline for B blocks already in buffers.input,
+    after wait if given, no host sync:
+       secrets [B][L] -> fill_blocks [B][L2] -> ntt_inverse2 -> zero_extend [B][L3] -> ntt_transform3
+       -> transpose_shares [R][B]
+    the queue is in order, so the kernels follow each other.
+    Randomness for the unused transform points is drawn on the device,
+    Philox keyed once per context from the host CSPRNG,
+    every call takes the next call number so the stream never repeats within the context
+    */
+    unsafe fn enqueue_share(&self, queue: &Queue, buffers: &ShareBuffers<T::Limb>, B: usize, rows: ShareRows, wait: Option<&Event>) {
         let L2 = self.degree2;
-        let L2_bit_mum = ((L2 as f64).log2().trunc() as u64)
-                                    ;
         let L3 = self.degree3;
-        let B = self.V / self.L;
-        println!("V = {:?}, B = {}, L = {}", self.V, B, self.L);
-        
-        let ref mut ocl_pq = self.pro_que;
-        let mut secret_blocks: Vec<u64> = Vec::new();
-        let mut rng = thread_rng();
-        for i in 0..B {
-            for j in 0..self.L {
-                secret_blocks.push(secrets[i*self.L+j]);
-            }
-            /* Pack randomness for unused transform points
-            randomness is no greater than max of T to prevent overflow
-            */
-            for _ in self.L..L2 {
-                secret_blocks.push(rng.gen_range(0u64, self.prime));
-            }
+        let (first, R) = rows.range(self.N, L3);
+
+        // the counter has no room for the high half of the block index
+        assert!((B as u64) < 1 << 32);
+        let call = self.calls.fetch_add(1, Ordering::Relaxed);
+        // 128 bit builds draw with a mask instead
+        let P = self.prime.to_u128();
+        let zone = if T::LIMBS == 1 { (P * (u64::MAX as u128 / P)) as u64 } else { 0 };
+
+        let fill = &self.kernels["fill_blocks"];
+        fill.set_arg(0, &buffers.input).unwrap();
+        fill.set_arg(1, &buffers.source).unwrap();
+        fill.set_arg(2, self.L as u64).unwrap();
+        fill.set_arg(3, L2 as u64).unwrap();
+        fill.set_arg(4, self.philox_key[0]).unwrap();
+        fill.set_arg(5, self.philox_key[1]).unwrap();
+        fill.set_arg(6, call).unwrap();
+        fill.set_arg(7, zone).unwrap();
+        fill.set_arg(8, self.prime.arg()).unwrap();
+        let mut cmd = fill.cmd().queue(queue).global_work_size(B);
+        if let Some(event) = wait {
+            cmd = cmd.ewait(event);
+        }
+        cmd.enq().unwrap();
+
+        let L2_inv = self.device_constant(self.inverse(L2 as u128));
+        self.enqueue_ntt(queue, "ntt_inverse2", &buffers.source, &self.twiddles.inverse2, L2, B, 2, Some(L2_inv));
+
+        let extend = &self.kernels["zero_extend"];
+        extend.set_arg(0, &buffers.source).unwrap();
+        extend.set_arg(1, &buffers.polys).unwrap();
+        extend.set_arg(2, L2 as u64).unwrap();
+        extend.set_arg(3, L3 as u64).unwrap();
+        extend.cmd().queue(queue).global_work_size(B).enq().unwrap();
+
+        self.enqueue_ntt(queue, "ntt_transform3", &buffers.polys, &self.twiddles.forward3, L3, B, 3, None);
+
+        let transpose = &self.kernels["transpose_shares"];
+        transpose.set_arg(0, &buffers.polys).unwrap();
+        transpose.set_arg(1, &buffers.shares).unwrap();
+        transpose.set_arg(2, B as u64).unwrap();
+        transpose.set_arg(3, L3 as u64).unwrap();
+        transpose.set_arg(4, first as u64).unwrap();
+        transpose.set_arg(5, self.prime.arg()).unwrap();
+        transpose.cmd().queue(queue).global_work_size((B, R)).enq().unwrap();
+    }
+
+    /* Input Format:
+       [[s00, s01, ..., s0b],   //shares of party 0
+        [s10, s11, ..., s1b],   //shares of party 1
+        ...
+        [sm0, sm1, ..., smb]]   //shares of party m
+    together with the evaluation point of every party,
+    any subset of at least degree2 parties.
+    One work item per block applies the Lagrange coefficients
+    of the share points, the coefficients stay on the device per point set
+    */
+    pub fn reconstruct(&mut self, shares: &[Vec<T>], shares_point: &[T]) -> Vec<T> {
+        assert!(shares.iter().all(|s| s.len() == self.V / self.L));
+        self.reconstruct_blocks(shares, shares_point)
+    }
+
+    // any number of blocks, as many as every party holds shares
+    fn reconstruct_blocks(&mut self, shares: &[Vec<T>], shares_point: &[T]) -> Vec<T> {
+        let B = if shares.is_empty() { 0 } else { shares[0].len() };
+        let M = shares_point.len();
+        assert!(shares.len() == M);
+        assert!(M >= self.degree2);
+        assert!(M <= self.degree3);
+
+        if !self.plans.contains_key(shares_point) {
+            let coeffs = self.plan_coeffs(shares_point);
+            let buffer = upload(&self.pro_que, &T::to_limbs(&coeffs)).unwrap();
+            self.plans.insert(shares_point.to_vec(), buffer);
         }
-        println!("secret_blocks {:?}\n roots2 {:?}", secret_blocks, self.roots2);
-
-        // set work dimension
-        ocl_pq.set_dims(B);
-        // copy matrix to device
-        let mut source: Buffer<u64>;
-        let roots2: Buffer<u64>;
-        let roots3: Buffer<u64>;
-        unsafe {
-            source = Buffer::new(
-                &ocl_pq.queue().clone(),
-                core::MEM_READ_WRITE | core::MEM_COPY_HOST_PTR, 
-                SpatialDims::One(B * L2), 
-                Some(&secret_blocks)
-            ).unwrap();
-            roots2 = Buffer::new(
-                &ocl_pq.queue().clone(),
-                core::MEM_READ_WRITE | core::MEM_COPY_HOST_PTR, 
-                SpatialDims::One(L2), 
-                Some(&self.roots2)
-            ).unwrap();
-            roots3 = Buffer::new(
-                &ocl_pq.queue().clone(),
-                core::MEM_READ_WRITE | core::MEM_COPY_HOST_PTR, 
-                SpatialDims::One(L3), 
-                Some(&self.roots3)
-            ).unwrap();
 
+        // party major as received, no transposition on the host
+        let mut flat_shares: Vec<T::Limb> = Vec::with_capacity(M * B * T::LIMBS);
+        for s in shares {
+            assert!(s.len() == B);
+            flat_shares.extend(T::to_limbs(s).iter());
         }
+        let source = self.pool.get("received", M * B * T::LIMBS);
+        let secrets = self.pool.get("secrets", B * self.L * T::LIMBS);
+        source.write(&flat_shares).len(M * B * T::LIMBS).enq().unwrap();
+
+        let kernel = &self.kernels["pss_reconstruct"];
+        kernel.set_arg(0, &source).unwrap();
+        kernel.set_arg(1, &self.plans[shares_point]).unwrap();
+        kernel.set_arg(2, &secrets).unwrap();
+        kernel.set_arg(3, M as u64).unwrap();
+        kernel.set_arg(4, B as u64).unwrap();
+        kernel.set_arg(5, self.L as u64).unwrap();
+        kernel.set_arg(6, self.prime.arg()).unwrap();
 
-        println!("Enqueuing ntt_inverse2 kernel");
-        let kern_start = time::get_time();
-        let mut kernel = ocl_pq.kernel_builder("ntt_inverse2")
-            .arg(&source)
-            .arg(&roots2)
-            .arg(L2 as u64)
-            .arg(self.roots2[1].clone())
-            .arg(L2_bit_mum)
-            .arg(self.prime)
-            .build()
-            .unwrap();
-        // Enqueue kernel: send to device and run it
         unsafe {
-            kernel
-            // 1 poly / local work goup
-            //.set_default_local_work_size((1, L2).into())
-            .enq()
-            .unwrap();
+            kernel.cmd().global_work_size(B).enq().unwrap();
         }
-        println!("{:?}", kernel);
-        ocl_pq.queue().finish();
-        print_elapsed("total elapsed", kern_start);
-
-        println!("Buffer reads [B*L2]");
-        let buff_start = time::get_time();
-        // Read dests from the device into dest_buffer's local vector:
-        let mut poly = vec![0u64; (L2*B) as usize];
-        source.read(&mut poly).enq().unwrap();
-        print_elapsed("queue unfinished", buff_start);
-        ocl_pq.queue().finish();
-        print_elapsed("queue finished", buff_start);
-        println!("source {:?}", source);
-  
-        //__________________________________________________
-   
-        for i in 0..B {
-            for _ in L2 ..L3 {
-                poly.insert(i*L3, 0u64);
-            }
+        self.pro_que.queue().finish().unwrap();
+
+        /* Output Format
+           [s0, ..., sv]
+        */
+        let mut ret = vec![T::Limb::default(); B * self.L * T::LIMBS];
+        secrets.read(&mut ret).len(B * self.L * T::LIMBS).enq().unwrap();
+        T::from_limbs(&ret)
+    }
+
+    /* coefficients for the secrets of every block, row major [L][M],
+    pss_compare's plan while products fit in u128, direct Lagrange above
+    */
+    fn plan_coeffs(&self, shares_point: &[T]) -> Vec<T> {
+        let P = self.prime.to_u128();
+        let secret_roots: Vec<u128> = self.roots2[..self.L].iter()
+            .map(|r| r.to_u128())
+            .collect();
+        let points: Vec<u128> = shares_point.iter().map(|x| x.to_u128()).collect();
+        let coeffs = if P <= u64::MAX as u128 {
+            ReconstructionPlan::new(&points, &secret_roots, P).coeffs().to_vec()
+        } else {
+            lagrange_coeffs128(&points, &secret_roots, P)
+        };
+        coeffs.iter()
+            .map(|c| self.device_constant(*c))
+            .collect()
+    }
+
+    /* Batched transforms, one poly per work item or work group
+       [[a00, ..., a0n], ..., [am0, ..., amn]]
+    every poly has degree2 (radix 2) or degree3 (radix 3) coefficients,
+    the trigit count of the radix 3 kernels is fixed at build time
+    */
+    pub fn transform2(&mut self, polys: &[Vec<T>]) -> Vec<Vec<T>> {
+        self.ntt_batch("ntt_transform2", polys, 2, false)
+    }
+
+    pub fn inverse2(&mut self, polys: &[Vec<T>]) -> Vec<Vec<T>> {
+        self.ntt_batch("ntt_inverse2", polys, 2, true)
+    }
+
+    pub fn transform3(&mut self, polys: &[Vec<T>]) -> Vec<Vec<T>> {
+        self.ntt_batch("ntt_transform3", polys, 3, false)
+    }
+
+    pub fn inverse3(&mut self, polys: &[Vec<T>]) -> Vec<Vec<T>> {
+        self.ntt_batch("ntt_inverse3", polys, 3, true)
+    }
+
+    // the inverse kernels scale by L^-1
+    fn ntt_batch(&mut self, name: &str, polys: &[Vec<T>], radix: usize, inverse: bool) -> Vec<Vec<T>> {
+        let L = if radix == 2 { self.degree2 } else { self.degree3 };
+        let B = polys.len();
+        if B == 0 {
+            return Vec::new();
+        }
+        let n = B * L * T::LIMBS;
+
+        let mut flat: Vec<T::Limb> = Vec::with_capacity(n);
+        for poly in polys {
+            assert!(poly.len() == L);
+            flat.extend(T::to_limbs(poly).iter());
         }
-        assert!(poly.len() == B * L3);
-        //println!("poly inserted to L3 {:?}", poly);
+        let source = self.pool.get("ntt", n);
+        source.write(&flat).len(n).enq().unwrap();
 
+        let twiddles = match (radix, inverse) {
+            (2, false) => &self.twiddles.forward2,
+            (2, true) => &self.twiddles.inverse2,
+            (_, false) => &self.twiddles.forward3,
+            (_, true) => &self.twiddles.inverse3,
+        };
+        let L_inv = if inverse { Some(self.device_constant(self.inverse(L as u128))) } else { None };
 
         unsafe {
-            source = Buffer::new(
-                &ocl_pq.queue().clone(),
-                core::MEM_READ_WRITE | core::MEM_COPY_HOST_PTR, 
-                SpatialDims::One(B * L3), 
-                Some(&poly)
-            ).unwrap();
+            self.enqueue_ntt(self.pro_que.queue(), name, &source, twiddles, L, B, radix, L_inv);
         }
+        self.pro_que.queue().finish().unwrap();
 
-        println!("Enqueuing ntt_transform3 kernel");
-        let kern_start = time::get_time();
-        let mut kernel = ocl_pq.kernel_builder("ntt_transform3")
-            .arg(&source)
-            .arg(&roots3)
-            .arg(L3 as u64)
-            .arg(self.prime)
-            .build()
-            .unwrap();
-        // Enqueue kernel: send to device and run it
-        unsafe {
-            kernel
-            .enq()
-            .unwrap();
+        source.read(&mut flat).len(n).enq().unwrap();
+        flat.chunks(L * T::LIMBS).map(T::from_limbs).collect()
+    }
+
+    /* B polys of length L in source, args in the order of kernel.rs:
+       source, twiddles, L, [L_inv], [log2(L) for radix 2], P, [__local buf], [trace, trace_len]
+    the _group variant gets one work group per poly, see group_size
+    */
+    unsafe fn enqueue_ntt(&self, queue: &Queue, name: &str, source: &Buffer<T::Limb>, twiddles: &Buffer<T::Limb>,
+                          L: usize, B: usize, radix: usize, L_inv: Option<T>) {
+        let group = self.use_work_group(name, L, B, radix);
+        let kernel = if group {
+            &self.kernels[&format!("{}_group", name)]
+        } else {
+            &self.kernels[name]
+        };
+
+        kernel.set_arg(0, source).unwrap();
+        kernel.set_arg(1, twiddles).unwrap();
+        kernel.set_arg(2, L as u64).unwrap();
+        let mut idx = 3;
+        if let Some(L_inv) = L_inv {
+            kernel.set_arg(idx, L_inv.arg()).unwrap();
+            idx += 1;
         }
-        println!("{:?}", kernel);
-        ocl_pq.queue().finish();
-        print_elapsed("total elapsed", kern_start);
-
-        println!("Buffer reads [B*L3]");
-        let buff_start = time::get_time();
-        // Read dests from the device into dest_buffer's local vector:
-        let mut res = vec![0u64; B * L3];
-        source.read(&mut res).enq().unwrap();
-        print_elapsed("queue unfinished", buff_start);
-        ocl_pq.queue().finish();
-        print_elapsed("queue finished", buff_start);
-        
-        let mut ret = vec![Vec::with_capacity(B); self.N];
-        for i in 0..self.N {
-            ret[i].extend(&res[i*B..(i+1)*B]);
+        if radix == 2 {
+            kernel.set_arg(idx, (L as f64).log2().trunc() as u64).unwrap();
+            idx += 1;
+        }
+        kernel.set_arg(idx, self.prime.arg()).unwrap();
+        if group {
+            idx += 1;
+        }
+        if let Some(ref trace) = self.trace {
+            kernel.set_arg(idx + 1, &trace.records).unwrap();
+            kernel.set_arg(idx + 2, &trace.len).unwrap();
+        }
+
+        if group {
+            let S = self.group_size(name, L, radix);
+            kernel.cmd().queue(queue).global_work_size(B * S).local_work_size(S).enq().unwrap();
+        } else {
+            kernel.cmd().queue(queue).global_work_size(B).enq().unwrap();
         }
-        ret
     }
 
-    // pub fn share2(&mut self, secrets: &[T]) -> Vec<Vec<u64>> {   u64 = ((L2 as f64).log2().trunc() as u64)
-    //                                 ;
-    //     let L3 = self.degree3;
-    //     let B = self.V / self.L;
-    //     println!("V = {:?}, B = {}, L = {}", self.V, B, self.L);
-        
-    //     let ref mut ocl_pq = self.pro_que;
-    //     let mut secret_blocks: Vec<T> = Vec::new();
-    //     let mut rng = thread_rng();
-    //     for i in 0..B {
-    //         for j in 0..self.L {
-    //             secret_blocks.push(secrets[i*self.L+j]);
-    //         }
-    //          Pack randomness for unused transform points
-    //         randomness is no greater than max of T to prevent overflow
-            
-    //         for _ in self.L..L2 {
-    //             secret_blocks.push(rng.gen_range(0u64, self.prime));
-    //         }
-    //     }
-    //     let mut ret: Vec<Vec<T>> = vec![Vec::with_capacity(B); self.N];
-    //     for (i, block) in secret_blocks.iter().enumerate() {
-    //         /* use radix2_DFT to from the poly
-    //         */
-    //         let mut poly = ntt::inverse2(block.to_vec(), self.prime, &self.roots2);
-    //         for _ in L2 ..L3 {
-    //             poly.push(0u64);
-    //         }
-    //         /* share with radix3_DFT
-    //         */
-    //         let mut shares = ntt::transform3(poly, self.prime, &self.roots3);
-    //         ret[i] = shares[1..self.N+1].to_vec();
-    //     }
-    //     retu64    // }
-
-    // Comput rootThrees
-    // Shares should in order
-    pub fn reconstruct(&mut self, shares: &Vec<Vec<u64>>) -> Vec<u64> {
-        assert!(shares.len() >= self.roots3.len());
-        let B = self.V/self.L;
-        let mut transposed = vec![Vec::with_capacity(B); self.N];
-        for i in 0..B {
-            transposed[i] = shares.iter().map(|s: &Vec<u64>| s[i]).collect::<Vec<u64>>();
+    fn use_work_group(&self, name: &str, L: usize, B: usize, radix: usize) -> bool {
+        match self.kernel_mode {
+            KernelMode::PerWorkItem => false,
+            KernelMode::WorkGroup => true,
+            KernelMode::Auto => {
+                (L * T::bytes()) as u64 <= self.device.local_mem_size
+                    && (B as u64) < self.compute_units * AUTO_POLYS_PER_UNIT
+                    && self.group_size(name, L, radix) >= (L / radix).min(AUTO_MIN_GROUP_SIZE)
+            }
         }
-        let mut ret = Vec::new();
-        for t in transposed {
-            ret.extend(lagrange_interpolation(&self.roots3, &t, &self.roots2, self.prime));
+    }
+
+    // up to L/radix items, capped by what the _group kernel of name can launch
+    fn group_size(&self, name: &str, L: usize, radix: usize) -> usize {
+        (L / radix).min(self.group_sizes[&format!("{}_group", name)]).max(1)
+    }
+}
+
+// data buffers of one share pipeline, taken from a pool, limbs Limbs per element
+#[cfg(feature = "opencl")]
+struct ShareBuffers<Limb: OclPrm> {
+    input: Buffer<Limb>,
+    source: Buffer<Limb>,
+    polys: Buffer<Limb>,
+    shares: Buffer<Limb>,
+}
+
+#[cfg(feature = "opencl")]
+impl<Limb: OclPrm> ShareBuffers<Limb> {
+    fn take(pool: &mut BufferPool<Limb>, B: usize, L: usize, L2: usize, L3: usize, R: usize, limbs: usize) -> ShareBuffers<Limb> {
+        ShareBuffers {
+            input: pool.get("input", B * L * limbs),
+            source: pool.get("source", B * L2 * limbs),
+            polys: pool.get("polys", B * L3 * limbs),
+            shares: pool.get("shares", R * B * limbs),
         }
-        ret
+    }
+}
+
+// read only device copy of a host table
+#[cfg(feature = "opencl")]
+fn upload<L: OclPrm>(pro_que: &ProQue, data: &[L]) -> ocl::Result<Buffer<L>> {
+    Buffer::<L>::builder()
+        .queue(pro_que.queue().clone())
+        .flags(core::MemFlags::new().read_only())
+        .len(data.len())
+        .copy_host_slice(data)
+        .build()
+}
+
+/* Every kernel is built once with placeholder arguments,
+   the calls rebind them with set_arg in the order of kernel.rs
+   debug builds give the NTT kernels the two trace args,
+   element args (P, L_inv) have the type of T
+*/
+#[cfg(feature = "opencl")]
+fn build_kernels<T: OclElem>(pro_que: &ProQue, degree2: usize, degree3: usize, debug: bool) -> ocl::Result<HashMap<String, ocl::Kernel>> {
+    let none = None::<&Buffer<T::Limb>>;
+    let elem = T::from_u128(0).arg();
+    let mut kernels = HashMap::new();
+
+    kernels.insert("fill_blocks".to_string(), pro_que.kernel_builder("fill_blocks")
+        .arg(none).arg(none)
+        .arg(0u64).arg(0u64).arg(0u32).arg(0u32).arg(0u64).arg(0u64).arg(elem)
+        .build()?);
+    kernels.insert("zero_extend".to_string(), pro_que.kernel_builder("zero_extend")
+        .arg(none).arg(none)
+        .arg(0u64).arg(0u64)
+        .build()?);
+    kernels.insert("transpose_shares".to_string(), pro_que.kernel_builder("transpose_shares")
+        .arg(none).arg(none)
+        .arg(0u64).arg(0u64).arg(0u64).arg(elem)
+        .build()?);
+    kernels.insert("pss_reconstruct".to_string(), pro_que.kernel_builder("pss_reconstruct")
+        .arg(none).arg(none).arg(none)
+        .arg(0u64).arg(0u64).arg(0u64).arg(elem)
+        .build()?);
+
+    let ntts = [("ntt_transform2", degree2, 2, false), ("ntt_inverse2", degree2, 2, true),
+                ("ntt_transform3", degree3, 3, false), ("ntt_inverse3", degree3, 3, true)];
+    for &(name, L, radix, inverse) in ntts.iter() {
+        for &group in [false, true].iter() {
+            let full_name = if group { format!("{}_group", name) } else { name.to_string() };
+            let mut builder = pro_que.kernel_builder(full_name.clone());
+            builder.arg(none).arg(none).arg(0u64);
+            if inverse {
+                builder.arg(elem);
+            }
+            if radix == 2 {
+                builder.arg(0u64);
+            }
+            builder.arg(elem);
+            if group {
+                builder.arg_local::<T::Limb>(L * T::LIMBS);
+            }
+            if debug {
+                builder.arg(None::<&Buffer<u64>>).arg(None::<&Buffer<u32>>);
+            }
+            kernels.insert(full_name, builder.build()?);
+        }
+    }
+    Ok(kernels)
+}
+
+/* CL_KERNEL_WORK_GROUP_SIZE of every _group kernel, below the device maximum
+   when the registers of two limb elements or the __local buffer run out first,
+   the device maximum if the driver does not say
+*/
+#[cfg(feature = "opencl")]
+fn group_sizes(pro_que: &ProQue, kernels: &HashMap<String, ocl::Kernel>, device_max: usize) -> ocl::Result<HashMap<String, usize>> {
+    let mut sizes = HashMap::new();
+    for (name, kernel) in kernels.iter().filter(|(name, _)| name.ends_with("_group")) {
+        let size = match kernel.wg_info(pro_que.device(), KernelWorkGroupInfo::WorkGroupSize)? {
+            KernelWorkGroupInfoResult::WorkGroupSize(size) => size.min(device_max),
+            _ => device_max,
+        };
+        sizes.insert(name.clone(), size);
+    }
+    Ok(sizes)
+}
+
+#[cfg(feature = "opencl")]
+impl<T: OclElem> SharingBackend for OclContext<T> {
+    type Elem = T;
+
+    fn share(&mut self, secrets: &[T]) -> Vec<Vec<T>> {
+        OclContext::share(self, secrets)
+    }
+
+    fn reconstruct(&mut self, shares: &[Vec<T>], shares_point: &[T]) -> Vec<T> {
+        OclContext::reconstruct(self, shares, shares_point)
+    }
+
+    fn prime(&self) -> T {
+        self.prime
+    }
+
+    fn share_point(&self, party: usize) -> T {
+        self.roots3[party + 1]
+    }
+
+    fn degree2(&self) -> usize {
+        self.degree2
+    }
+
+    fn degree3(&self) -> usize {
+        self.degree3
+    }
+
+    fn total_len(&self) -> usize {
+        self.V
     }
 
+    fn packing_len(&self) -> usize {
+        self.L
+    }
+
+    fn num_shares(&self) -> usize {
+        self.N
+    }
 }
 
 pub fn lagrange_interpolation(points: &Vec<u64>, values: &Vec<u64>, roots: &Vec<u64>, P:u64) -> Vec<u64> 
@@ -345,9 +824,9 @@ pub fn lagrange_interpolation(points: &Vec<u64>, values: &Vec<u64>, roots: &Vec<
                 d = d % P;
             }
         }
-        d = d.modpow(P - 2u64, P);
         denominators.push(d);
     }
+    let denominators = batch_inverse(&denominators, P);
 
     let mut evals: Vec<u64> = Vec::new();
     for r in roots {
@@ -372,10 +851,3 @@ pub fn lagrange_interpolation(points: &Vec<u64>, values: &Vec<u64>, roots: &Vec<
     
     evals
 }
-
-fn print_elapsed(title: &str, start: time::Timespec) {
-    let time_elapsed = time::get_time() - start;
-    let elapsed_ms = time_elapsed.num_microseconds();
-    let separator = if title.len() > 0 { ": " } else { "" };
-    println!("    {}{}: {} us", title, separator, elapsed_ms.unwrap());
-}
\ No newline at end of file
@@ -1,4 +1,3 @@
-extern crate ocl;
 use ocl_test::*;
 use ocl_test::util::ModPow;
 
@@ -30,10 +29,26 @@ fn main() {
     let total_len = args[2].parse::<usize>().unwrap();
     let packing_len = args[3].parse::<usize>().unwrap();
 
-    let mut pss = OclContext::new(p, 
-        (r2 as u128).modpow(r2_divisor as u128, p as u128) as u64, 
-        (r3 as u128).modpow(9u128, p as u128) as u64, 
-        512/r2_divisor, 729/9, total_len, packing_len, 729/9).unwrap();
+    let root2 = (r2 as u128).modpow(r2_divisor as u128, p as u128) as u64;
+    let root3 = (r3 as u128).modpow(9u128, p as u128) as u64;
+    let degree2 = 512/r2_divisor;
+    let degree3 = 729/9;
+    let num_shares = degree3 - 1;
+
+    /* Same call sites for both engines,
+    pass "cpu" as 4th argument to skip OpenCL,
+    without a device (or the opencl feature) PssContext shares on the CPU anyway
+    */
+    let use_cpu = args.get(4).map(|a| a == "cpu").unwrap_or(false);
+    let mut pss = match use_cpu {
+        false => PssContext::new(p, root2, root3, 
+            degree2, degree3, total_len, packing_len, num_shares),
+        true => PssContext::new_cpu(p, root2, root3, 
+            degree2, degree3, total_len, packing_len, num_shares),
+    };
+    if let Some(reason) = pss.fallback_reason() {
+        println!("CPU engine: {}", reason);
+    }
     //prime: u64, root2: u64, root3:u64, degree2: usize, degree3: usize, 
     //total_len: usize, packing_len: usize, num_shares: usize
     
@@ -45,6 +60,7 @@ fn main() {
 
     let shares = pss.share(&secrets);
     println!("Result {:?}", shares);
-    let reconstruction = pss.reconstruct(&shares);
+    let points: Vec<u64> = (0..pss.num_shares()).map(|i| pss.share_point(i)).collect();
+    let reconstruction = pss.reconstruct(&shares, &points);
     println!("Result {:?}", &reconstruction);
 }
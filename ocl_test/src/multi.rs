@@ -0,0 +1,218 @@
+use std::thread;
+
+use rand::{thread_rng, Rng};
+use time;
+
+use crate::{OclContext, OclElem, OclError, ShareRows, SharingBackend};
+use crate::device::{DeviceSelector, list_devices};
+
+// blocks shared on every device by calibrate() in new
+const CALIBRATION_BLOCKS: usize = 64;
+
+/* One OclContext per device, the blocks of a call are split
+   in proportion to the throughput each device showed in calibrate()
+   and the per-device shares are merged back into the usual layout
+*/
+pub struct MultiOclContext<T: OclElem = u64> {
+    contexts: Vec<OclContext<T>>,
+    // blocks per second, measured
+    throughput: Vec<f64>,
+
+    prime: T,
+    degree2: usize,
+    degree3: usize,
+    V: usize,
+    L: usize,
+    N: usize,
+}
+
+impl<T: OclElem> MultiOclContext<T> {
+
+    /* Every device the selector accepts, e.g. DeviceSelector::Type(DeviceType::GPU),
+    OclError::NoDevice if there is none, the first error of a device otherwise
+    */
+    pub fn new(selector: &DeviceSelector, prime: T, root2: T, root3: T,
+               degree2: usize, degree3: usize, total_len: usize, packing_len: usize, num_shares: usize) -> Result<MultiOclContext<T>, OclError> {
+        let candidates = list_devices();
+        let mut contexts = Vec::new();
+        for device in selector.select_all(&candidates) {
+            contexts.push(OclContext::with_device(&DeviceSelector::Index(device.index), prime, root2, root3,
+                degree2, degree3, total_len, packing_len, num_shares)?);
+        }
+        if contexts.is_empty() {
+            return Err(OclError::NoDevice {
+                selector: format!("{:?}", selector),
+                available: candidates.iter().map(|c| c.to_string()).collect(),
+            });
+        }
+
+        let mut multi = MultiOclContext {
+            throughput: vec![1.0; contexts.len()],
+            contexts: contexts,
+
+            prime: prime,
+            degree2: degree2,
+            degree3: degree3,
+            V: total_len,
+            L: packing_len,
+            N: num_shares,
+        };
+        multi.calibrate(CALIBRATION_BLOCKS);
+        Ok(multi)
+    }
+
+    pub fn contexts(&self) -> &[OclContext<T>] {
+        &self.contexts
+    }
+
+    pub fn throughput(&self) -> &[f64] {
+        &self.throughput
+    }
+
+    /* Time the share pipeline on blocks random blocks per device,
+    the first run also warms up the pooled buffers
+    */
+    pub fn calibrate(&mut self, blocks: usize) {
+        let mut rng = thread_rng();
+        let P = self.prime.to_u128();
+        let secrets: Vec<T> = (0..blocks * self.L).map(|_| T::from_u128(rng.gen_range(0u128, P))).collect();
+        for (i, context) in self.contexts.iter_mut().enumerate() {
+            context.share_blocks(&secrets, ShareRows::Distributed);
+            let start = time::get_time();
+            context.share_blocks(&secrets, ShareRows::Distributed);
+            let us = (time::get_time() - start).num_microseconds().unwrap_or(1).max(1);
+            self.throughput[i] = blocks as f64 * 1e6 / us as f64;
+        }
+    }
+
+    /* Blocks per device summing to B, by throughput,
+    the rounding remainder goes to the fastest devices
+    */
+    pub fn split(&self, B: usize) -> Vec<usize> {
+        let total: f64 = self.throughput.iter().sum();
+        let mut counts: Vec<usize> = self.throughput.iter()
+            .map(|t| (B as f64 * t / total).floor() as usize)
+            .collect();
+        let mut order: Vec<usize> = (0..counts.len()).collect();
+        order.sort_by(|a, b| self.throughput[*b].partial_cmp(&self.throughput[*a]).unwrap());
+        let mut left = B - counts.iter().sum::<usize>();
+        for i in order.iter().cycle() {
+            if left == 0 {
+                break;
+            }
+            counts[*i] += 1;
+            left -= 1;
+        }
+        counts
+    }
+
+    pub fn share(&mut self, secrets: &[T]) -> Vec<Vec<T>> {
+        self.share_rows(secrets, ShareRows::Distributed)
+    }
+
+    // [R][B] as OclContext::share_rows, every device runs on its own thread
+    pub fn share_rows(&mut self, secrets: &[T], rows: ShareRows) -> Vec<Vec<T>> {
+        assert!(secrets.len() == self.V);
+        let L = self.L;
+        let counts = self.split(self.V / L);
+
+        let mut parts: Vec<&[T]> = Vec::with_capacity(counts.len());
+        let mut offset = 0;
+        for c in counts.iter() {
+            parts.push(&secrets[offset * L..(offset + c) * L]);
+            offset += c;
+        }
+
+        let results: Vec<Vec<Vec<T>>> = thread::scope(|s| {
+            let handles: Vec<_> = self.contexts.iter_mut().zip(parts.into_iter())
+                .map(|(context, part)| s.spawn(move || {
+                    if part.is_empty() {
+                        Vec::new()
+                    } else {
+                        context.share_blocks(part, rows)
+                    }
+                }))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        // row r is the concatenation of row r of every device
+        let (_, R) = rows.range(self.N, self.degree3);
+        let mut ret: Vec<Vec<T>> = vec![Vec::with_capacity(self.V / L); R];
+        for part in results {
+            for (r, row) in part.into_iter().enumerate() {
+                ret[r].extend(row);
+            }
+        }
+        ret
+    }
+
+    // same split as share, so every device gets back the blocks it shared
+    pub fn reconstruct(&mut self, shares: &[Vec<T>], shares_point: &[T]) -> Vec<T> {
+        let B = self.V / self.L;
+        assert!(shares.iter().all(|s| s.len() == B));
+        let counts = self.split(B);
+
+        let mut parts: Vec<Vec<Vec<T>>> = Vec::with_capacity(counts.len());
+        let mut offset = 0;
+        for c in counts.iter() {
+            parts.push(shares.iter().map(|s| s[offset..offset + c].to_vec()).collect());
+            offset += c;
+        }
+
+        let results: Vec<Vec<T>> = thread::scope(|s| {
+            let handles: Vec<_> = self.contexts.iter_mut().zip(parts.into_iter())
+                .zip(counts.iter())
+                .map(|((context, part), c)| s.spawn(move || {
+                    if *c == 0 {
+                        Vec::new()
+                    } else {
+                        context.reconstruct_blocks(&part, shares_point)
+                    }
+                }))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        results.concat()
+    }
+}
+
+impl<T: OclElem> SharingBackend for MultiOclContext<T> {
+    type Elem = T;
+
+    fn share(&mut self, secrets: &[T]) -> Vec<Vec<T>> {
+        MultiOclContext::share(self, secrets)
+    }
+
+    fn reconstruct(&mut self, shares: &[Vec<T>], shares_point: &[T]) -> Vec<T> {
+        MultiOclContext::reconstruct(self, shares, shares_point)
+    }
+
+    fn prime(&self) -> T {
+        self.prime
+    }
+
+    fn share_point(&self, party: usize) -> T {
+        self.contexts[0].share_point(party)
+    }
+
+    fn degree2(&self) -> usize {
+        self.degree2
+    }
+
+    fn degree3(&self) -> usize {
+        self.degree3
+    }
+
+    fn total_len(&self) -> usize {
+        self.V
+    }
+
+    fn packing_len(&self) -> usize {
+        self.L
+    }
+
+    fn num_shares(&self) -> usize {
+        self.N
+    }
+}
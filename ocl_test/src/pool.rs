@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+
+use ocl::{Buffer, Queue};
+use ocl::core;
+use ocl::traits::OclPrm;
+
+/* Device buffers reused across calls, one per purpose,
+   grown to the largest length asked for and never shrunk
+   so steady traffic of the same batch size allocates nothing
+   L is the limb type of the elements, OclElem::Limb
+*/
+pub struct BufferPool<L: OclPrm = u64> {
+    queue: Queue,
+    buffers: HashMap<&'static str, Buffer<L>>,
+}
+
+impl<L: OclPrm> BufferPool<L> {
+
+    pub fn new(queue: Queue) -> BufferPool<L> {
+        BufferPool {
+            queue: queue,
+            buffers: HashMap::new(),
+        }
+    }
+
+    // a buffer of at least len elements, contents left from the last use
+    pub fn get(&mut self, name: &'static str, len: usize) -> Buffer<L> {
+        let grow = match self.buffers.get(name) {
+            Some(buffer) => buffer.len() < len,
+            None => true,
+        };
+        if grow {
+            let buffer = Buffer::<L>::builder()
+                .queue(self.queue.clone())
+                .flags(core::MemFlags::new().read_write())
+                .len(len.max(1))
+                .build()
+                .unwrap();
+            self.buffers.insert(name, buffer);
+        }
+        self.buffers[name].clone()
+    }
+
+    // elements held on the device over all buffers
+    pub fn allocated(&self) -> usize {
+        self.buffers.values().map(|b| b.len()).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.buffers.clear();
+    }
+}
@@ -0,0 +1,310 @@
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use ocl::{Context, Program, ProQue, Queue};
+use ocl::enums::{DeviceInfo, ProgramInfo, ProgramInfoResult};
+use sha2::{Digest, Sha256};
+
+use crate::device::DeviceCandidate;
+use crate::error::OclError;
+use crate::kernel::*;
+use crate::util::{trigits_len, montgomery_inv, montgomery_r2, montgomery_inv128, montgomery_r2_128};
+use crate::trace::TRACE_CAPACITY;
+
+/* PSS_OCL_CACHE is the directory of cached program binaries,
+   $XDG_CACHE_HOME/pss_ocl or $HOME/.cache/pss_ocl if unset, "off" disables the cache
+   a binary runs with the secrets, so the cache is per user and unix only:
+   the directory is created 0700, and the directory and every binary must be
+   owned by the current user and not writable by group or others to be used
+*/
+pub const CACHE_ENV: &str = "PSS_OCL_CACHE";
+
+// PSS_OCL_PROFILE=debug builds OclContext::new and with_device with tracing
+pub const PROFILE_ENV: &str = "PSS_OCL_PROFILE";
+
+// PSS_OCL_REDUCTION=montgomery selects Reduction::Montgomery for the same constructors
+pub const REDUCTION_ENV: &str = "PSS_OCL_REDUCTION";
+
+/* Release compiles the tracing out of the kernels,
+   Debug records every butterfly output, see trace.rs
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BuildProfile {
+    Release,
+    Debug,
+}
+
+impl BuildProfile {
+    pub fn from_env() -> BuildProfile {
+        match env::var(PROFILE_ENV) {
+            Ok(ref value) if value.trim().eq_ignore_ascii_case("debug") => BuildProfile::Debug,
+            _ => BuildProfile::Release,
+        }
+    }
+}
+
+/* How mul_mod in the kernels reduces
+   Plain:      % for primes below 2^32, Barrett above
+   Montgomery: Montgomery products, the share pipeline keeps its data
+               in Montgomery form between fill_blocks and transpose_shares
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Reduction {
+    Plain,
+    Montgomery,
+}
+
+impl Reduction {
+    pub fn from_env() -> Reduction {
+        match env::var(REDUCTION_ENV) {
+            Ok(ref value) if value.trim().eq_ignore_ascii_case("montgomery") => Reduction::Montgomery,
+            _ => Reduction::Plain,
+        }
+    }
+}
+
+/* everything the kernels are specialized on, passed as -D build options,
+   L, log2(L) and P stay runtime args, the prime only picks the reduction constants
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct KernelParams {
+    pub prime: u128,
+    // OclElem::BITS of the context, 128 builds the ulong2 kernels
+    pub elem_bits: u32,
+    pub trigits: usize,
+    // twiddle tables in __constant instead of __global memory
+    pub twiddle_constant: bool,
+    pub profile: BuildProfile,
+    pub reduction: Reduction,
+}
+
+impl KernelParams {
+
+    pub fn new(degree3: usize, prime: u128, elem_bits: u32, twiddle_constant: bool,
+               profile: BuildProfile, reduction: Reduction) -> KernelParams {
+        KernelParams {
+            prime: prime,
+            elem_bits: elem_bits,
+            trigits: trigits_len(degree3),
+            twiddle_constant: twiddle_constant,
+            profile: profile,
+            reduction: reduction,
+        }
+    }
+
+    pub fn build_options(&self) -> String {
+        let mut options = vec![
+            format!("-D ELEM_BITS={}", self.elem_bits),
+            format!("-D TRIGITS={}", self.trigits),
+            format!("-D TWIDDLE={}", if self.twiddle_constant { "__constant" } else { "__global" }),
+        ];
+        if self.elem_bits == 128 {
+            options.extend(wide_options(self.prime));
+        } else if self.elem_bits == 32 {
+            // NARROW reduces the 64 bit product with %, no constants to pass
+            assert!(self.prime > 2 && self.prime < (1u128 << 32), "32 bit kernel primes must be below 2^32");
+        } else {
+            options.extend(mul_mod_options(self.prime as u64, self.reduction));
+        }
+        if self.profile == BuildProfile::Debug {
+            options.push("-D PSS_DEBUG".to_string());
+            options.push(format!("-D TRACE_CAPACITY={}", TRACE_CAPACITY));
+        }
+        options.join(" ")
+    }
+}
+
+/* Selects mul_mod in PSS_ARITH: Montgomery with -P^-1 and 2^128 mod P,
+   or the plain product below 2^32,
+   otherwise Barrett with n = bit length of P and mu = floor(2^2n / P)
+*/
+fn mul_mod_options(prime: u64, reduction: Reduction) -> Vec<String> {
+    assert!(prime > 2 && prime < (1u64 << 62), "kernel primes must be below 2^62");
+    if reduction == Reduction::Montgomery {
+        return vec![
+            "-D MONTGOMERY".to_string(),
+            format!("-D MONT_PINV={}UL", montgomery_inv(prime)),
+            format!("-D MONT_R2={}UL", montgomery_r2(prime)),
+        ];
+    }
+    if prime < (1u64 << 32) {
+        return vec!["-D SMALL_PRIME".to_string()];
+    }
+    let n = 64 - prime.leading_zeros();
+    let mu = (1u128 << (2 * n)) / prime as u128;
+    vec![
+        format!("-D BARRETT_BITS={}", n),
+        format!("-D BARRETT_MU={}UL", mu as u64),
+    ]
+}
+
+/* The 128 bit kernels: Montgomery constants split into limbs
+   and the mask that draws random limbs below the bit length of P
+*/
+fn wide_options(prime: u128) -> Vec<String> {
+    assert!(prime > 2 && prime < (1u128 << 127), "128 bit kernel primes must be below 2^127");
+    let pinv = montgomery_inv128(prime);
+    let r2 = montgomery_r2_128(prime);
+    let bits = 128 - prime.leading_zeros();
+    let mask = if bits == 128 { u128::MAX } else { (1u128 << bits) - 1 };
+    vec![
+        "-D MONTGOMERY".to_string(),
+        format!("-D MONT_PINV_LO={}UL", pinv as u64),
+        format!("-D MONT_PINV_HI={}UL", (pinv >> 64) as u64),
+        format!("-D MONT_R2_LO={}UL", r2 as u64),
+        format!("-D MONT_R2_HI={}UL", (r2 >> 64) as u64),
+        format!("-D WIDE_MASK_LO={}UL", mask as u64),
+        format!("-D WIDE_MASK_HI={}UL", (mask >> 64) as u64),
+    ]
+}
+
+// all kernels, specialized only through build options
+pub fn kernel_source() -> String {
+    [PSS_TRACE, PSS_ARITH, NTT_RADIX2, NTT_RADIX3, PSS_RECONSTRUCT, PSS_PIPELINE,
+     PSS_TRANSPOSE, PSS_RANDOM, NTT_GROUP].concat()
+}
+
+/* Program for params on device: the cached binary if there is one
+   that still builds, otherwise compiled from source and cached,
+   OclError::Build with the compiler output if the source does not compile
+*/
+pub fn build_pro_que(device: &DeviceCandidate, params: &KernelParams) -> Result<ProQue, OclError> {
+    let context = Context::builder()
+        .platform(device.platform)
+        .devices(device.device)
+        .build()
+        .map_err(|e| OclError::ocl(device, e))?;
+    let queue = Queue::new(&context, device.device, None)
+        .map_err(|e| OclError::ocl(device, e))?;
+    let options = params.build_options();
+    let path = cache_path(device, params);
+
+    let cached = path.as_ref()
+        .and_then(|p| read_private(p))
+        .and_then(|binary| {
+            Program::builder()
+                .devices(device.device)
+                .binaries(&[&binary[..]])
+                .cmplr_opt(options.clone())
+                .build(&context)
+                .ok()
+        });
+
+    let program = match cached {
+        Some(program) => program,
+        None => {
+            let source = kernel_source();
+            let program = Program::builder()
+                .devices(device.device)
+                .src(source.clone())
+                .cmplr_opt(options.clone())
+                .build(&context)
+                .map_err(|e| OclError::build(device, &options, e.to_string(), &source))?;
+            if let Some(p) = path {
+                store_binary(&program, &p);
+            }
+            program
+        }
+    };
+
+    Ok(ProQue::new(context, queue, program, None::<usize>))
+}
+
+/* One file per device, driver and parameter set, named by the SHA-256
+   of all of them, the source is part of the key so edited kernels are rebuilt,
+   None if the cache is off or its directory is not private
+*/
+fn cache_path(device: &DeviceCandidate, params: &KernelParams) -> Option<PathBuf> {
+    let dir = match env::var(CACHE_ENV) {
+        Ok(ref value) if value == "off" => return None,
+        Ok(value) => PathBuf::from(value),
+        Err(_) => match (env::var_os("XDG_CACHE_HOME"), env::var_os("HOME")) {
+            (Some(cache), _) if !cache.is_empty() => PathBuf::from(cache).join("pss_ocl"),
+            (_, Some(home)) if !home.is_empty() => PathBuf::from(home).join(".cache").join("pss_ocl"),
+            _ => return None,
+        },
+    };
+    if !private_dir(&dir) {
+        return None;
+    }
+
+    let driver = device.device.info(DeviceInfo::DriverVersion).map(|v| v.to_string()).unwrap_or_default();
+    let mut hasher = Sha256::new();
+    for part in [&device.platform_name, &device.name, &device.vendor, &driver,
+                 &params.build_options(), &kernel_source()].iter() {
+        // length prefixed, so no two keys run together the same way
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part.as_bytes());
+    }
+    let name: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+    Some(dir.join(format!("{}.bin", name)))
+}
+
+/* Written to a temporary file next to path and renamed over it,
+   so a concurrent reader sees the old binary or the whole new one,
+   a failed write only costs the next start a compile
+*/
+fn store_binary(program: &Program, path: &Path) {
+    if let Ok(ProgramInfoResult::Binaries(binaries)) = program.info(ProgramInfo::Binaries) {
+        if let Some(binary) = binaries.first() {
+            let tmp = path.with_extension(format!("tmp{}", process::id()));
+            let written = create_private(&tmp)
+                .and_then(|mut file| file.write_all(binary).and_then(|_| file.sync_all()))
+                .and_then(|_| fs::rename(&tmp, path));
+            if written.is_err() {
+                let _ = fs::remove_file(&tmp);
+            }
+        }
+    }
+}
+
+// the cached binary, None if missing or not private
+fn read_private(path: &Path) -> Option<Vec<u8>> {
+    let mut file = fs::File::open(path).ok()?;
+    // checked on the open file, so it cannot be swapped after the check
+    if !is_private(&file.metadata().ok()?) {
+        return None;
+    }
+    let mut binary = Vec::new();
+    file.read_to_end(&mut binary).ok()?;
+    Some(binary)
+}
+
+#[cfg(unix)]
+fn private_dir(dir: &Path) -> bool {
+    use std::os::unix::fs::DirBuilderExt;
+    let _ = fs::DirBuilder::new().recursive(true).mode(0o700).create(dir);
+    fs::metadata(dir).map(|m| m.is_dir() && is_private(&m)).unwrap_or(false)
+}
+
+// no owner and mode to check, so no cache
+#[cfg(not(unix))]
+fn private_dir(_dir: &Path) -> bool {
+    false
+}
+
+// owned by the current user, not writable by group or others
+#[cfg(unix)]
+fn is_private(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    metadata.uid() == unsafe { libc::geteuid() } && metadata.mode() & 0o022 == 0
+}
+
+#[cfg(not(unix))]
+fn is_private(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn create_private(path: &Path) -> std::io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> std::io::Result<fs::File> {
+    fs::OpenOptions::new().write(true).create_new(true).open(path)
+}
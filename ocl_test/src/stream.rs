@@ -0,0 +1,143 @@
+use std::borrow::Cow;
+use std::sync::mpsc::Sender;
+
+use ocl::{Event, Queue};
+use ocl::traits::OclPrm;
+
+use crate::{OclContext, OclElem, ShareRows, ShareBuffers};
+use crate::pool::BufferPool;
+
+// shares of the blocks first_block.., [R][blocks], R as in ShareRows
+pub struct ShareChunk<T = u64> {
+    pub first_block: usize,
+    pub blocks: usize,
+    pub shares: Vec<Vec<T>>,
+}
+
+// a chunk whose download was enqueued but not waited for
+struct Pending<'a, L: OclPrm> {
+    first_block: usize,
+    blocks: usize,
+    read: Event,
+    host: Vec<L>,
+    // limbs of the upload, alive until the chunk is done
+    _input: Cow<'a, [L]>,
+}
+
+// one command queue with its own buffers, at most one chunk in flight
+struct Lane<'a, L: OclPrm> {
+    queue: Queue,
+    pool: BufferPool<L>,
+    pending: Option<Pending<'a, L>>,
+}
+
+impl<T: OclElem> OclContext<T> {
+
+    /* Blocks per chunk: the largest chunk buffer [B][L3] within MaxMemAllocSize
+    and the buffers of all lanes together within half the global memory
+    */
+    pub fn chunk_blocks(&self, lanes: usize) -> usize {
+        let per_block = ((self.L + self.degree2 + 2 * self.degree3) * T::bytes()) as u64;
+        let by_alloc = self.device.max_mem_alloc_size / (self.degree3 * T::bytes()) as u64;
+        let by_global = self.device.global_mem_size / 2 / (per_block * lanes as u64);
+        by_alloc.min(by_global).max(1) as usize
+    }
+
+    /* Share any whole number of blocks chunk by chunk.
+    The lanes take turns, so the upload of one chunk,
+    the kernels of the next and the download of another overlap;
+    each chunk waits on the event of its own upload,
+    the host only waits on a download before reusing its lane.
+    Chunks reach the callback in order
+    */
+    pub fn share_stream<F>(&mut self, secrets: &[T], rows: ShareRows, lanes: usize, mut callback: F)
+    where F: FnMut(ShareChunk<T>)
+    {
+        assert!(secrets.len() % self.L == 0);
+        assert!(lanes > 0);
+        let L = self.L;
+        let total_blocks = secrets.len() / L;
+        let chunk = self.chunk_blocks(lanes);
+        let (_, R) = rows.range(self.N, self.degree3);
+
+        let context = self.pro_que.context().clone();
+        let device = self.pro_que.device();
+        let mut lanes: Vec<Lane<T::Limb>> = (0..lanes).map(|_| {
+            let queue = Queue::new(&context, device, None).unwrap();
+            Lane {
+                pool: BufferPool::new(queue.clone()),
+                queue: queue,
+                pending: None,
+            }
+        }).collect();
+        let n = lanes.len();
+
+        let mut first_block = 0;
+        let mut turn = 0;
+        while first_block < total_blocks {
+            let B = chunk.min(total_blocks - first_block);
+            let lane = &mut lanes[turn % n];
+            if let Some(done) = lane.pending.take() {
+                callback(finish(done));
+            }
+
+            let buffers = ShareBuffers::take(&mut lane.pool, B, L, self.degree2, self.degree3, R, T::LIMBS);
+            let mut uploaded = Event::empty();
+            let mut read = Event::empty();
+            let input = T::to_limbs(&secrets[first_block * L..(first_block + B) * L]);
+            // the download lands here while the host moves on
+            let mut host = vec![T::Limb::default(); R * B * T::LIMBS];
+            unsafe {
+                buffers.input.write(&input[..])
+                    .queue(&lane.queue)
+                    .block(false)
+                    .enew(&mut uploaded)
+                    .enq().unwrap();
+                self.enqueue_share(&lane.queue, &buffers, B, rows, Some(&uploaded));
+                buffers.shares.read(&mut host)
+                    .queue(&lane.queue)
+                    .len(R * B * T::LIMBS)
+                    .block(false)
+                    .enew(&mut read)
+                    .enq().unwrap();
+            }
+            lane.queue.flush().unwrap();
+
+            lane.pending = Some(Pending {
+                first_block: first_block,
+                blocks: B,
+                read: read,
+                host: host,
+                _input: input,
+            });
+            first_block += B;
+            turn += 1;
+        }
+
+        // drain in the order the chunks were issued
+        for i in 0..n {
+            if let Some(done) = lanes[(turn + i) % n].pending.take() {
+                callback(finish(done));
+            }
+        }
+    }
+
+    /* share_stream into a channel, e.g. for a writer thread
+    that stores the chunks while the device works on the next ones
+    */
+    pub fn share_stream_to(&mut self, secrets: &[T], rows: ShareRows, lanes: usize, sender: &Sender<ShareChunk<T>>) {
+        self.share_stream(secrets, rows, lanes, |chunk| {
+            // a dropped receiver only means nobody wants the rest
+            let _ = sender.send(chunk);
+        });
+    }
+}
+
+fn finish<T: OclElem>(pending: Pending<T::Limb>) -> ShareChunk<T> {
+    pending.read.wait_for().unwrap();
+    ShareChunk {
+        first_block: pending.first_block,
+        blocks: pending.blocks,
+        shares: pending.host.chunks(pending.blocks * T::LIMBS).map(T::from_limbs).collect(),
+    }
+}
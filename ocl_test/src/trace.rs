@@ -0,0 +1,219 @@
+use ocl::{Buffer, Queue};
+use ocl::core;
+
+use pss_compare::ntt::{bit_reverse2, bit_reverse3, radix2_stage, radix3_stage};
+
+use crate::{OclContext, Reduction};
+use crate::util::from_montgomery_slice;
+
+// records the device keeps per build, later ones are counted and dropped
+pub const TRACE_CAPACITY: usize = 1 << 20;
+
+/* One butterfly output as written by the kernels (PSS_TRACE in kernel.rs):
+   poly within the batch, stage as butterfly span (m for radix 2, 3i for radix 3),
+   index within the poly and the value after that stage
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TraceRecord {
+    pub poly: u64,
+    pub stage: u64,
+    pub index: u64,
+    pub value: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transform {
+    Transform2,
+    Inverse2,
+    Transform3,
+    Inverse3,
+}
+
+// a record only one side has, or the two values differ
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceMismatch {
+    pub device: Option<TraceRecord>,
+    pub cpu: Option<TraceRecord>,
+}
+
+// device side of a debug build, the NTT kernels get both as trailing args
+pub(crate) struct TraceBuffers {
+    pub(crate) records: Buffer<u64>,
+    pub(crate) len: Buffer<u32>,
+}
+
+impl TraceBuffers {
+
+    pub(crate) fn new(queue: &Queue) -> TraceBuffers {
+        TraceBuffers {
+            records: Buffer::<u64>::builder()
+                .queue(queue.clone())
+                .flags(core::MemFlags::new().write_only())
+                .len(4 * TRACE_CAPACITY)
+                .build()
+                .unwrap(),
+            len: Buffer::<u32>::builder()
+                .queue(queue.clone())
+                .flags(core::MemFlags::new().read_write())
+                .len(1)
+                .copy_host_slice(&[0u32])
+                .build()
+                .unwrap(),
+        }
+    }
+
+    // decode what was recorded so far and start over
+    fn take(&self) -> Vec<TraceRecord> {
+        let mut len = vec![0u32];
+        self.len.read(&mut len).enq().unwrap();
+        let count = len[0] as usize;
+        if count > TRACE_CAPACITY {
+            println!("trace: {} records dropped, capacity {}", count - TRACE_CAPACITY, TRACE_CAPACITY);
+        }
+        let count = count.min(TRACE_CAPACITY);
+
+        let mut words = vec![0u64; 4 * count];
+        if count > 0 {
+            self.records.read(&mut words).len(4 * count).enq().unwrap();
+        }
+        self.len.write(&[0u32][..]).enq().unwrap();
+        decode(&words)
+    }
+}
+
+pub fn decode(words: &[u64]) -> Vec<TraceRecord> {
+    words.chunks(4)
+        .map(|r| TraceRecord {
+            poly: r[0],
+            stage: r[1],
+            index: r[2],
+            value: r[3],
+        })
+        .collect()
+}
+
+/* The records the CPU ntt produces for the same polys and twiddle table,
+   stage by stage with pss_compare::ntt, computed in u128
+   inverse transforms are traced before the scaling, as on the device
+*/
+pub fn cpu_trace(transform: Transform, polys: &[Vec<u64>], table: &[u64], P: u64) -> Vec<TraceRecord> {
+    let P = P as u128;
+    let table: Vec<u128> = table.iter().map(|w| *w as u128).collect();
+    let mut records = Vec::new();
+    for (poly, coeffs) in polys.iter().enumerate() {
+        let mut a: Vec<u128> = coeffs.iter().map(|c| *c as u128).collect();
+        let L = a.len();
+        let mut record = |a: &[u128], stage: usize| {
+            for (index, value) in a.iter().enumerate() {
+                records.push(TraceRecord {
+                    poly: poly as u64,
+                    stage: stage as u64,
+                    index: index as u64,
+                    value: *value as u64,
+                });
+            }
+        };
+        match transform {
+            Transform::Transform2 | Transform::Inverse2 => {
+                bit_reverse2(&mut a);
+                let mut m = 2;
+                while m <= L {
+                    radix2_stage(&mut a, P, &table, m);
+                    record(&a, m);
+                    m *= 2;
+                }
+            }
+            Transform::Transform3 | Transform::Inverse3 => {
+                bit_reverse3(&mut a);
+                let mut i = 1;
+                while i < L {
+                    radix3_stage(&mut a, P, &table, i);
+                    record(&a, 3 * i);
+                    i *= 3;
+                }
+            }
+        }
+    }
+    records
+}
+
+// records keyed by (poly, stage, index) that are missing on one side or differ
+pub fn compare(device: &[TraceRecord], cpu: &[TraceRecord]) -> Vec<TraceMismatch> {
+    let key = |r: &TraceRecord| (r.poly, r.stage, r.index);
+    let mut device = device.to_vec();
+    let mut cpu = cpu.to_vec();
+    device.sort();
+    cpu.sort();
+
+    let mut mismatches = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < device.len() || j < cpu.len() {
+        let (d, c) = (device.get(i), cpu.get(j));
+        match (d, c) {
+            (Some(d), Some(c)) if key(d) == key(c) => {
+                if d.value != c.value {
+                    mismatches.push(TraceMismatch { device: Some(*d), cpu: Some(*c) });
+                }
+                i += 1;
+                j += 1;
+            }
+            (Some(d), Some(c)) if key(d) < key(c) => {
+                mismatches.push(TraceMismatch { device: Some(*d), cpu: None });
+                i += 1;
+            }
+            (Some(d), None) => {
+                mismatches.push(TraceMismatch { device: Some(*d), cpu: None });
+                i += 1;
+            }
+            (_, Some(c)) => {
+                mismatches.push(TraceMismatch { device: None, cpu: Some(*c) });
+                j += 1;
+            }
+            (None, None) => break,
+        }
+    }
+    mismatches
+}
+
+// debug builds are u64 only, see OclContext::with_reduction
+impl OclContext<u64> {
+
+    // records since the last call, None for release builds
+    pub fn take_trace(&mut self) -> Option<Vec<TraceRecord>> {
+        self.pro_que.queue().finish().unwrap();
+        self.trace.as_ref().map(|t| t.take())
+    }
+
+    /* Run transform on the device in a debug build and
+    replay it with the CPU ntt on the twiddles the device used,
+    empty if every stage of every poly agrees
+    */
+    pub fn check_transform(&mut self, transform: Transform, polys: &[Vec<u64>]) -> Vec<TraceMismatch> {
+        assert!(self.trace.is_some(), "tracing needs BuildProfile::Debug");
+        self.take_trace();
+
+        match transform {
+            Transform::Transform2 => self.transform2(polys),
+            Transform::Inverse2 => self.inverse2(polys),
+            Transform::Transform3 => self.transform3(polys),
+            Transform::Inverse3 => self.inverse3(polys),
+        };
+        let device = self.take_trace().unwrap();
+
+        let twiddles = match transform {
+            Transform::Transform2 => &self.twiddles.forward2,
+            Transform::Inverse2 => &self.twiddles.inverse2,
+            Transform::Transform3 => &self.twiddles.forward3,
+            Transform::Inverse3 => &self.twiddles.inverse3,
+        };
+        let mut table = vec![0u64; twiddles.len()];
+        twiddles.read(&mut table).enq().unwrap();
+        // the standalone transforms run on plain data, only the table is in Montgomery form
+        if self.params.reduction == Reduction::Montgomery {
+            from_montgomery_slice(&mut table, self.prime);
+        }
+
+        let cpu = cpu_trace(transform, polys, &table, self.prime);
+        compare(&device, &cpu)
+    }
+}
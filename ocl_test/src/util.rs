@@ -1,4 +1,5 @@
 
+
 pub trait ModPow {
     fn modpow(&self, exponent: Self, modulus: Self) -> Self;
 }
@@ -40,6 +41,9 @@ impl ModPow for u32 {
     modpow!(u32);
 }
 
+// Montgomery's trick, shared with the CPU engine
+pub use pss_compare::util::batch_inverse;
+
 pub trait HasMax {
     fn max() -> Self;
 }
@@ -71,3 +75,234 @@ pub fn trigits_len(n: usize) -> usize {
     }
     result
 }
+
+/* Montgomery form x * 2^64 mod P as used by kernels built with
+   Reduction::Montgomery, P odd and below 2^62
+*/
+
+// -P^-1 mod 2^64, Newton doubles the correct low bits from the 3 of P * P = 1 mod 8
+pub fn montgomery_inv(P: u64) -> u64 {
+    assert!(P % 2 == 1, "Montgomery form needs an odd modulus");
+    let mut inv = P;
+    for _ in 0..5 {
+        inv = inv.wrapping_mul(2u64.wrapping_sub(P.wrapping_mul(inv)));
+    }
+    inv.wrapping_neg()
+}
+
+// 2^128 mod P, to_mont on the device multiplies with it
+pub fn montgomery_r2(P: u64) -> u64 {
+    let r = ((1u128 << 64) % P as u128) as u64;
+    ((r as u128 * r as u128) % P as u128) as u64
+}
+
+pub fn to_montgomery(x: u64, P: u64) -> u64 {
+    (((x as u128) << 64) % P as u128) as u64
+}
+
+pub fn from_montgomery(x: u64, P: u64) -> u64 {
+    montgomery_reduce(x as u128, P, montgomery_inv(P))
+}
+
+pub fn to_montgomery_slice(values: &mut [u64], P: u64) {
+    for v in values.iter_mut() {
+        *v = to_montgomery(*v, P);
+    }
+}
+
+pub fn from_montgomery_slice(values: &mut [u64], P: u64) {
+    let P_inv = montgomery_inv(P);
+    for v in values.iter_mut() {
+        *v = montgomery_reduce(*v as u128, P, P_inv);
+    }
+}
+
+// x * 2^-64 mod P for x < P * 2^64, the host side of mul_mod in PSS_ARITH
+pub fn montgomery_reduce(x: u128, P: u64, P_inv: u64) -> u64 {
+    let m = (x as u64).wrapping_mul(P_inv);
+    let t = ((x + m as u128 * P as u128) >> 64) as u64;
+    if t >= P { t - P } else { t }
+}
+
+/* Host arithmetic of OclContext, for any element type in u128,
+   the product is exact below 2^64, shift and add above, P below 2^127
+*/
+pub fn mul_mod128(a: u128, b: u128, P: u128) -> u128 {
+    let (a, mut b) = (a % P, b % P);
+    if P <= u64::MAX as u128 {
+        return a * b % P;
+    }
+    let mut a = a;
+    let mut res = 0u128;
+    while b > 0 {
+        if b & 1 == 1 {
+            res = add_mod128(res, a, P);
+        }
+        a = add_mod128(a, a, P);
+        b >>= 1;
+    }
+    res
+}
+
+fn add_mod128(a: u128, b: u128, P: u128) -> u128 {
+    let s = a + b;
+    if s >= P { s - P } else { s }
+}
+
+pub fn pow_mod128(base: u128, exponent: u128, P: u128) -> u128 {
+    let mut base = base % P;
+    let mut exp = exponent;
+    let mut res = 1u128 % P;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            res = mul_mod128(res, base, P);
+        }
+        base = mul_mod128(base, base, P);
+        exp >>= 1;
+    }
+    res
+}
+
+/* Montgomery form x * 2^128 mod P of the 128 bit kernels,
+   the counterparts of the 64 bit routines above
+*/
+pub fn montgomery_inv128(P: u128) -> u128 {
+    assert!(P % 2 == 1, "Montgomery form needs an odd modulus");
+    let mut inv = P;
+    for _ in 0..6 {
+        inv = inv.wrapping_mul(2u128.wrapping_sub(P.wrapping_mul(inv)));
+    }
+    inv.wrapping_neg()
+}
+
+// 2^256 mod P
+pub fn montgomery_r2_128(P: u128) -> u128 {
+    let r = (u128::MAX % P + 1) % P;
+    mul_mod128(r, r, P)
+}
+
+pub fn to_montgomery128(x: u128, P: u128) -> u128 {
+    let r = (u128::MAX % P + 1) % P;
+    mul_mod128(x, r, P)
+}
+
+/* Lagrange coefficients coeffs[k * M + i] = l_i(roots[k]) of the points,
+   the layout of pss_compare::ReconstructionPlan for primes it cannot handle,
+   O(M^2 + L*M) products with mul_mod128
+*/
+pub fn lagrange_coeffs128(points: &[u128], roots: &[u128], P: u128) -> Vec<u128> {
+    let M = points.len();
+    let sub = |a: u128, b: u128| if a >= b { a - b } else { a + P - b };
+
+    // w_i = 1 / prod_{j != i} (x_i - x_j), all M inverted at once
+    let mut denominators = Vec::with_capacity(M);
+    for i in 0..M {
+        let mut d = 1u128;
+        for j in 0..M {
+            if i != j {
+                d = mul_mod128(d, sub(points[i], points[j]), P);
+            }
+        }
+        denominators.push(d);
+    }
+    let weights = batch_inverse128(&denominators, P);
+
+    let mut coeffs = vec![0u128; roots.len() * M];
+    for (k, r) in roots.iter().enumerate() {
+        let row = &mut coeffs[k * M..(k + 1) * M];
+        if let Some(i) = points.iter().position(|x| x == r) {
+            row[i] = 1;
+            continue;
+        }
+        // l_i(r) = m(r) * w_i / (r - x_i) with m(r) = prod_j (r - x_j)
+        let mut m_r = 1u128;
+        for x in points {
+            m_r = mul_mod128(m_r, sub(*r, *x), P);
+        }
+        let diffs: Vec<u128> = points.iter().map(|x| sub(*r, *x)).collect();
+        let diff_invs = batch_inverse128(&diffs, P);
+        for i in 0..M {
+            row[i] = mul_mod128(mul_mod128(m_r, diff_invs[i], P), weights[i], P);
+        }
+    }
+    coeffs
+}
+
+// batch_inverse with mul_mod128, values non-zero mod P
+pub fn batch_inverse128(values: &[u128], P: u128) -> Vec<u128> {
+    if values.is_empty() {
+        return Vec::new();
+    }
+    let mut prefix = Vec::with_capacity(values.len());
+    let mut acc = 1u128;
+    for v in values {
+        acc = mul_mod128(acc, *v, P);
+        prefix.push(acc);
+    }
+    assert!(acc != 0, "batch_inverse128 of zero");
+
+    let mut inv = pow_mod128(acc, P - 2, P);
+    let mut ret = vec![0u128; values.len()];
+    for i in (1..values.len()).rev() {
+        ret[i] = mul_mod128(inv, prefix[i - 1], P);
+        inv = mul_mod128(inv, values[i], P);
+    }
+    ret[0] = inv;
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    // 2^127 - 1, the largest prime OclContext<u128> takes
+    const P127: u128 = (1 << 127) - 1;
+    const P62: u64 = 4610415792919412737;
+
+    #[test]
+    fn montgomery_inv_is_negated_inverse() {
+        assert_eq!(P62.wrapping_mul(montgomery_inv(P62)), u64::MAX);
+        assert_eq!(3224862721u64.wrapping_mul(montgomery_inv(3224862721)), u64::MAX);
+        assert_eq!(P127.wrapping_mul(montgomery_inv128(P127)), u128::MAX);
+        let P = P62 as u128;
+        assert_eq!(P.wrapping_mul(montgomery_inv128(P)), u128::MAX);
+    }
+
+    #[test]
+    fn montgomery_roundtrip() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..1000 {
+            let x = rng.gen_range(0, P62);
+            assert_eq!(from_montgomery(to_montgomery(x, P62), P62), x);
+        }
+        let mut values: Vec<u64> = (0..100).map(|_| rng.gen_range(0, P62)).collect();
+        let expected = values.clone();
+        to_montgomery_slice(&mut values, P62);
+        from_montgomery_slice(&mut values, P62);
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn montgomery128_roundtrip() {
+        let mut rng = rand::thread_rng();
+        for &P in &[P127, P62 as u128] {
+            let r = to_montgomery128(1, P);
+            let r_inv = pow_mod128(r, P - 2, P);
+            assert_eq!(montgomery_r2_128(P), mul_mod128(r, r, P));
+            for _ in 0..100 {
+                let x = rng.gen_range(0, P);
+                assert_eq!(mul_mod128(to_montgomery128(x, P), r_inv, P), x);
+            }
+        }
+    }
+
+    #[test]
+    fn batch_inverse128_inverts() {
+        let mut rng = rand::thread_rng();
+        let values: Vec<u128> = (0..50).map(|_| rng.gen_range(1, P127)).collect();
+        for (v, inv) in values.iter().zip(batch_inverse128(&values, P127)) {
+            assert_eq!(mul_mod128(*v, inv, P127), 1);
+        }
+    }
+}
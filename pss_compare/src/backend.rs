@@ -0,0 +1,76 @@
+use crate::PackedSecretSharing;
+
+/* Common interface of the sharing engines (CPU here, OpenCL in ocl_test)
+   so callers can pick one at runtime behind a Box<dyn SharingBackend<Elem = _>>
+
+   share:
+	  [x0, ..., xv] -> [[s00, ..., s0b], ..., [sm0, ..., smb]]	//shares per party
+   reconstruct:
+	  shares of any subset of parties together with their share_point
+*/
+pub trait SharingBackend {
+	type Elem;
+
+	fn share(&mut self, secrets: &[Self::Elem]) -> Vec<Vec<Self::Elem>>;
+	fn reconstruct(&mut self, shares: &[Vec<Self::Elem>], shares_point: &[Self::Elem]) -> Vec<Self::Elem>;
+
+	fn prime(&self) -> Self::Elem;
+	// evaluation point of the shares handed to party i
+	fn share_point(&self, party: usize) -> Self::Elem;
+
+	fn degree2(&self) -> usize;
+	fn degree3(&self) -> usize;
+	fn total_len(&self) -> usize;
+	fn packing_len(&self) -> usize;
+	fn num_shares(&self) -> usize;
+}
+
+/* The CPU engine computes in a type twice as wide as the elements
+   it hands out, so products of two elements never overflow
+*/
+macro_rules! cpu_backend {
+	($wide: ty, $elem: ty) => (
+		impl SharingBackend for PackedSecretSharing<$wide> {
+			type Elem = $elem;
+
+			fn share(&mut self, secrets: &[$elem]) -> Vec<Vec<$elem>> {
+				PackedSecretSharing::share(self, secrets)
+			}
+
+			fn reconstruct(&mut self, shares: &[Vec<$elem>], shares_point: &[$elem]) -> Vec<$elem> {
+				self.reconstruct_cached(shares, shares_point)
+			}
+
+			fn prime(&self) -> $elem {
+				self.prime as $elem
+			}
+
+			fn share_point(&self, party: usize) -> $elem {
+				self.rootTable3[party + 1] as $elem
+			}
+
+			fn degree2(&self) -> usize {
+				self.degree2
+			}
+
+			fn degree3(&self) -> usize {
+				self.degree3
+			}
+
+			fn total_len(&self) -> usize {
+				self.V
+			}
+
+			fn packing_len(&self) -> usize {
+				self.L
+			}
+
+			fn num_shares(&self) -> usize {
+				self.N
+			}
+		}
+	)
+}
+
+cpu_backend!(u64, u32);
+cpu_backend!(u128, u64);
@@ -1,6 +1,9 @@
 #![allow(non_snake_case)]
 
 use std::convert::*;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
 use time;
 
 use core::fmt::Debug;
@@ -11,10 +14,22 @@ use rand::distributions::uniform::SampleUniform;
 use num_traits::{One, Zero};
 use num::traits::Unsigned;
 
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 
-mod ntt;
+
+pub mod ntt;
+pub mod backend;
+pub mod plan;
+pub mod poly;
+pub mod simd;
+pub mod twiddle;
 pub mod util;
 pub use util::*;
+pub use backend::SharingBackend;
+pub use plan::ReconstructionPlan;
+pub use simd::{Backend, BatchNtt};
+use twiddle::{stage_table2, stage_table3};
 
 #[derive(Clone, Debug)]
 pub struct PackedSecretSharing<T> {
@@ -24,6 +39,11 @@ pub struct PackedSecretSharing<T> {
 	root3: T,
 	pub rootTable2: Vec<T>,
 	pub rootTable3: Vec<T>,
+	// per-stage twiddles of the transforms in share and reconstruct, see twiddle.rs
+	stageTable2: Vec<T>,
+	inverseStageTable2: Vec<T>,
+	stageTable3: Vec<T>,
+	L2_inverse: T,
 	// degree of the sharing poly
 	degree2: usize,
 	degree3: usize,
@@ -31,10 +51,20 @@ pub struct PackedSecretSharing<T> {
 	V: usize,
 	L: usize,
 	N: usize,
+
+	// reconstruction plans keyed by the share points they were built for
+	plans: HashMap<Vec<T>, ReconstructionPlan<T>>,
+
+	// blocks are sharded over the pool, None runs them serially
+	threads: usize,
+	pool: Option<Arc<ThreadPool>>,
+
+	// vectorized ntt for primes that fit u64 lanes, None stays scalar
+	simd: Option<BatchNtt>,
 }
 
 impl<T> PackedSecretSharing<T>
-where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialOrd,
+where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialOrd + Hash + Eq + Send + Sync + AsU64,
 {
 
 	pub fn new(prime: T, root2: T, root3:T, 
@@ -52,11 +82,26 @@ where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialO
 		for i in 0..degree3 as u64 {
 			rootTable3.push(root3.modpow((i as u64).into(), prime));
 		}
+		let to_u64 = |table: &Vec<T>| -> Option<Vec<u64>> {
+			table.iter().map(|x| x.as_u64()).collect()
+		};
+		// w^-i = w^(L-i)
+		let inverseRootTable2: Vec<T> = (0..degree2).map(|i| rootTable2[(degree2 - i) % degree2]).collect();
+		let L2_inverse = batch_inverse(&[(degree2 as u64).into()], prime)[0];
+		let simd = match (prime.as_u64(), to_u64(&rootTable2), to_u64(&rootTable3)) {
+			(Some(p), Some(r2), Some(r3)) => BatchNtt::new(p, &r2, &r3),
+			_ => None,
+		};
+
 		PackedSecretSharing {
 
 			prime: prime,
 			root2: root2,
 			root3: root3,
+			stageTable2: stage_table2(&rootTable2),
+			inverseStageTable2: stage_table2(&inverseRootTable2),
+			stageTable3: stage_table3(&rootTable3),
+			L2_inverse,
 			rootTable2: rootTable2,
 			rootTable3: rootTable3,
 
@@ -65,6 +110,68 @@ where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialO
 			V: total_len,
 			L: packing_len,
 			N: num_shares,
+
+			plans: HashMap::new(),
+
+			threads: 1,
+			pool: None,
+
+			simd,
+		}
+	}
+
+	/* Switch between the vectorized and the scalar ntt,
+	enabling has no effect if the cpu or the prime is not supported
+	*/
+	pub fn set_simd(&mut self, enabled: bool) {
+		self.simd = None;
+		if enabled {
+			let rootTable2: Option<Vec<u64>> = self.rootTable2.iter().map(|x| x.as_u64()).collect();
+			let rootTable3: Option<Vec<u64>> = self.rootTable3.iter().map(|x| x.as_u64()).collect();
+			if let (Some(p), Some(r2), Some(r3)) = (self.prime.as_u64(), rootTable2, rootTable3) {
+				self.simd = BatchNtt::new(p, &r2, &r3);
+			}
+		}
+	}
+
+	pub fn simd_backend(&self) -> Backend {
+		match &self.simd {
+			Some(simd) => simd.backend(),
+			None => Backend::Scalar,
+		}
+	}
+
+	/* Number of threads share and reconstruct spread the blocks over,
+	1 runs serially on the calling thread, 0 uses one thread per core
+	*/
+	pub fn set_threads(&mut self, threads: usize) {
+		self.pool = if threads == 1 {
+			None
+		} else {
+			let pool = ThreadPoolBuilder::new()
+				.num_threads(threads)
+				.build()
+				.expect("Build thread pool");
+			Some(Arc::new(pool))
+		};
+		self.threads = match &self.pool {
+			Some(pool) => pool.current_num_threads(),
+			None => 1,
+		};
+	}
+
+	pub fn threads(&self) -> usize {
+		self.threads
+	}
+
+	// f(i) for every block i, on the pool if there is one
+	fn map_blocks<R, F>(&self, B: usize, f: F) -> Vec<R>
+	where R: Send,
+		  F: Fn(usize) -> R + Send + Sync
+	{
+		match &self.pool {
+			Some(pool) => pool.install(|| (0..B).into_par_iter().map(f).collect()),
+			None => (0..B).map(f).collect(),
 		}
 	}
 
@@ -101,17 +208,38 @@ where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialO
 
 		println!("Start ");
         let kern_start = time::get_time();
-		let mut ret: Vec<Vec<U>> = vec![vec![U::zero(); B]; self.N];
-		for (i, block) in secret_blocks.iter().enumerate() {
-			/* use radix2_DFT to from the poly
-			*/
-			let mut poly = ntt::inverse2(block.to_vec(), self.prime, &self.rootTable2);
-			for _ in L2 ..L3 {
-				poly.push(T::zero());
+		let block_shares: Vec<Vec<T>> = match &self.simd {
+			Some(simd) => {
+				/* Same pipeline on groups of blocks,
+				one block per vector lane
+				*/
+				let lanes = simd.lanes();
+				let groups = self.map_blocks(B.div_ceil(lanes), |g| {
+					let group: Vec<Vec<u64>> = secret_blocks[g * lanes..B.min((g + 1) * lanes)].iter()
+						.map(|block| block.iter().map(|x| (*x % self.prime).as_u64().unwrap()).collect())
+						.collect();
+					simd.share_blocks(&group)
+				});
+				groups.into_iter()
+					.flatten()
+					.map(|shares| shares.into_iter().map(T::from).collect())
+					.collect()
 			}
-			/* share with radix3_DFT
-			*/
-			let mut shares = ntt::transform3(poly, self.prime, &self.rootTable3);
+			None => self.map_blocks(B, |i| {
+				/* use radix2_DFT to from the poly
+				*/
+				let mut poly = ntt::inverse2_staged(secret_blocks[i].to_vec(), self.prime,
+					&self.inverseStageTable2, self.L2_inverse);
+				for _ in L2 ..L3 {
+					poly.push(T::zero());
+				}
+				/* share with radix3_DFT
+				*/
+				ntt::transform3_staged(poly, self.prime, &self.stageTable3)
+			}),
+		};
+		let mut ret: Vec<Vec<U>> = vec![vec![U::zero(); B]; self.N];
+		for (i, shares) in block_shares.iter().enumerate() {
 			for j in 0..self.N {
 				ret[j][i] = shares[j + 1].try_into().unwrap();
 			}
@@ -163,7 +291,7 @@ where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialO
 		for (i, block) in secret_blocks.iter().enumerate() {
 			/* use radix2_DFT to from the poly
 			*/
-			let mut poly = ntt::inverse2(block.to_vec(), self.prime, &self.rootTable2);
+			let poly = ntt::inverse2_staged(block.to_vec(), self.prime, &self.inverseStageTable2, self.L2_inverse);
 			polys.push(poly);
 		}
 		println!("{:?}, {}", polys.len(), polys[0].len());
@@ -178,7 +306,7 @@ where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialO
 			}
 			/* share with radix3_DFT
 			*/
-			let mut shares = ntt::transform3(poly, self.prime, &self.rootTable3);
+			let shares = ntt::transform3_staged(poly, self.prime, &self.stageTable3);
 			for j in 0..self.N {
 				ret[j][i] = shares[j + 1].try_into().unwrap();
 			}
@@ -229,16 +357,27 @@ where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialO
 			}
 		}
 		assert!(converted_ponts.len() == converted_shares[0].len());
-		/* Evaluate up till the secrets, split to disard randomness
-		Reconstruct each poly
+		/* Interpolate each poly through the subproduct tree of the points,
+		the tree only depends on the parties and is shared by all blocks.
+		Folding mod x^degree2 - 1 keeps the values on the radix2 roots,
+		evaluate up till the secrets, split to disard randomness
 		*/
+		let L2 = self.degree2;
+		let tree = poly::SubproductTree::new(&converted_ponts, self.prime);
+
+		let secrets_blocks: Vec<Vec<T>> = self.map_blocks(B, |i| {
+			let coeffs = tree.interpolate(&converted_shares[i]);
+			let mut folded = vec![T::zero(); L2];
+			for (j, c) in coeffs.into_iter().enumerate() {
+				folded[j % L2] = (folded[j % L2] + c) % self.prime;
+			}
+			let mut secrets_block: Vec<T> = ntt::transform2_staged(folded, self.prime, &self.stageTable2);
+			secrets_block.truncate(self.L);
+			secrets_block
+		});
 
 		let mut ret: Vec<U> = Vec::new();
-		for i in 0..B {
-			let mut secrets_block: Vec<T> = ntt::lagrange_interpolation(
-				&converted_ponts, &converted_shares[i], &self.rootTable2, self.prime
-			);
-			secrets_block.split_off(self.L);
+		for secrets_block in secrets_blocks {
 			for j in 0..self.L {
 				ret.push(secrets_block[j].try_into().unwrap());
 			}
@@ -248,6 +387,52 @@ where T: ModPow + Unsigned + Copy + Debug + From<u64> + SampleUniform + PartialO
 		*/
 		ret		
 	}
+
+	/* Plan for a fixed set of share points,
+	built on first use and cached for every later call with the same points
+	*/
+	pub fn plan(&mut self, shares_point: &[T]) -> &ReconstructionPlan<T> {
+		assert!(shares_point.len() >= self.degree2);
+		assert!(shares_point.len() <= self.degree3);
+		let prime = self.prime;
+		let roots = &self.rootTable2[..self.L];
+		self.plans.entry(shares_point.to_vec())
+			.or_insert_with(|| ReconstructionPlan::new(shares_point, roots, prime))
+	}
+
+	pub fn clear_plans(&mut self) {
+		self.plans.clear();
+	}
+
+	pub fn reconstruct_cached<U>(&mut self, shares: &[Vec<U>], shares_point: &[U]) -> Vec<U>
+	where  U: TryFrom<T> + Into<T> + Copy + HasMax + SampleUniform + Unsigned + Sync,
+	   	   <U as TryFrom<T>>::Error: Debug
+	{
+		/* Same formats as reconstruct,
+		every block is a matrix-vector product with the cached plan
+		*/
+		let V = self.V;
+		let B = self.V / self.L;
+		let M = shares_point.len();
+		assert!(shares.len() == M);
+
+		let converted_ponts: Vec<T> = shares_point.iter().map(|p| (*p).into()).collect();
+		self.plan(&converted_ponts);
+		let plan = &self.plans[&converted_ponts];
+
+		let secrets_blocks: Vec<Vec<T>> = self.map_blocks(B, |i| {
+			let column: Vec<T> = shares.iter().map(|s| s[i].into()).collect();
+			plan.apply(&column)
+		});
+
+		let mut ret: Vec<U> = Vec::with_capacity(V);
+		for secrets_block in secrets_blocks {
+			for s in secrets_block {
+				ret.push(s.try_into().unwrap());
+			}
+		}
+		ret
+	}
 }
 
 fn print_elapsed(title: &str, start: time::Timespec) {
@@ -255,4 +440,97 @@ fn print_elapsed(title: &str, start: time::Timespec) {
     let elapsed_ms = time_elapsed.num_microseconds();
     let separator = if title.len() > 0 { ": " } else { "" };
     println!("    {}{}: {} us", title, separator, elapsed_ms.unwrap());
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// 512th and 729th roots of unity, as in main.rs
+	const P32: u64 = 3224862721;
+	const P62: u128 = 4610415792919412737;
+
+	fn pss32(packing_len: usize, num_shares: usize) -> PackedSecretSharing<u64> {
+		PackedSecretSharing::new(P32, 889378889, 388768380, 512, 729, 4 * packing_len, packing_len, num_shares)
+	}
+
+	fn pss62(packing_len: usize, num_shares: usize) -> PackedSecretSharing<u128> {
+		PackedSecretSharing::new(P62, 1266473570726112470, 2230453091198852918, 512, 729, 4 * packing_len, packing_len, num_shares)
+	}
+
+	// parties first..first + count with their shares and points, a strict subset of all parties
+	fn subset<T, U>(pss: &PackedSecretSharing<T>, shares: &[Vec<U>], first: usize, count: usize) -> (Vec<Vec<U>>, Vec<U>)
+	where U: Clone + TryFrom<T>,
+		  T: Copy,
+		  <U as TryFrom<T>>::Error: Debug
+	{
+		let parties = first..first + count;
+		let points = parties.clone().map(|j| pss.rootTable3[j + 1].try_into().unwrap()).collect();
+		(shares[parties].to_vec(), points)
+	}
+
+	#[test]
+	fn share_reconstruct_u64() {
+		let mut pss = pss32(100, 600);
+		let secrets: Vec<u32> = (0..400).map(|i| i * i + 1).collect();
+		let shares = pss.share(&secrets);
+		let (shares, points) = subset(&pss, &shares, 50, 520);
+		assert_eq!(pss.reconstruct(&shares, &points), secrets);
+
+		// the scalar pipeline as well when share took the SIMD one
+		pss.set_simd(false);
+		let shares = pss.share(&secrets);
+		let (shares, points) = subset(&pss, &shares, 50, 520);
+		assert_eq!(pss.reconstruct(&shares, &points), secrets);
+	}
+
+	#[test]
+	fn share_reconstruct_u128() {
+		let mut pss = pss62(100, 600);
+		let secrets: Vec<u64> = (0..400).map(|i| (i * i + 1) << 40).collect();
+		let shares = pss.share(&secrets);
+		let (shares, points) = subset(&pss, &shares, 70, 512);
+		assert_eq!(pss.reconstruct(&shares, &points), secrets);
+	}
+
+	#[test]
+	fn reconstruct_cached_reuses_plan() {
+		let mut pss = pss32(100, 600);
+		let secrets: Vec<u32> = (0..400).map(|i| 3 * i + 7).collect();
+		let shares = pss.share(&secrets);
+		let (shares, points) = subset(&pss, &shares, 30, 540);
+		let key: Vec<u64> = points.iter().map(|p| *p as u64).collect();
+
+		assert_eq!(pss.reconstruct_cached(&shares, &points), secrets);
+		assert_eq!(pss.plans.len(), 1);
+		let coeffs = pss.plans[&key].coeffs().as_ptr();
+
+		assert_eq!(pss.reconstruct_cached(&shares, &points), secrets);
+		assert_eq!(pss.plans.len(), 1);
+		assert_eq!(pss.plans[&key].coeffs().as_ptr(), coeffs);
+	}
+
+	#[test]
+	fn threads_match_serial() {
+		let mut pss = pss32(100, 600);
+		let secrets: Vec<u32> = (0..400).map(|i| 5 * i + 2).collect();
+		let shares = pss.share(&secrets);
+		let (shares, points) = subset(&pss, &shares, 10, 560);
+		let serial = pss.reconstruct(&shares, &points);
+		let serial_cached = pss.reconstruct_cached(&shares, &points);
+		assert_eq!(serial, secrets);
+		assert_eq!(serial_cached, secrets);
+
+		for &threads in [4, 0].iter() {
+			pss.set_threads(threads);
+			// 0 is one per core, a single core machine still gets the pool
+			assert!(pss.pool.is_some());
+			assert!(threads == 0 || pss.threads() == threads);
+			assert_eq!(pss.reconstruct(&shares, &points), serial);
+			assert_eq!(pss.reconstruct_cached(&shares, &points), serial_cached);
+
+			let shares = pss.share(&secrets);
+			let (shares, points) = subset(&pss, &shares, 10, 560);
+			assert_eq!(pss.reconstruct(&shares, &points), secrets);
+		}
+	}
+}
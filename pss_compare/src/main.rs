@@ -38,5 +38,8 @@ fn main() {
         secrets[i] = (i * i + 1) as u128;
     }
 
-    pss.share_seperate(&secrets);
+    // share runs on the thread pool and the SIMD batch NTT where available,
+    // share_seperate is the serial scalar pipeline timed per stage
+    pss.set_threads(0);
+    pss.share(&secrets);
 }
\ No newline at end of file
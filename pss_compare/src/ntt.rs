@@ -4,6 +4,7 @@ use num_traits::*;
 use num_traits::{One, Zero};
 
 use crate::util::*;
+use crate::twiddle::*;
 
 
 //out-of-place transform
@@ -31,7 +32,9 @@ where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 
 
 	let L = b.len();
-	let w = rootTable[1].modpow(P - 2.into(), P);
+	let L_into: T = (L as u64).into();
+	let inverses = batch_inverse(&[rootTable[1], L_into], P);
+	let (w, L_inverse) = (inverses[0], inverses[1]);
 	let zero = T::zero();
 
 	//calculating inverse omegas
@@ -45,8 +48,6 @@ where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 
 	// F^-1(Y) = nX
 	// Thus divide output by n or multiply n^-1
-	let L_into: T = (L as u64).into();
-	let L_inverse: T = L_into.modpow(P - 2.into(), P);
 	for i in 0..L {
 		b[i] = b[i] * L_inverse % P;
 	}
@@ -59,7 +60,9 @@ where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 {
 
 	let L = b.len();
-	let w = rootTable[1].modpow(P - 2.into(), P);
+	let L_into: T = (L as u64).into();
+	let inverses = batch_inverse(&[rootTable[1], L_into], P);
+	let (w, L_inverse) = (inverses[0], inverses[1]);
 	let zero = T::zero();
 
 	//calculating inverse omegas
@@ -73,8 +76,6 @@ where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 
 	// F^-1(Y) = nX
 	// Thus divide output by n or multiply n^-1
-	let L_into: T = (L as u64).into();
-	let L_inverse = L_into.modpow(P - 2.into(), P);
 	for i in 0..L {
 		b[i] = b[i] * L_inverse % P;
 	}
@@ -82,60 +83,121 @@ where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 	b
 }
 
+/* The same transforms on stage tables built once by the caller (see twiddle.rs),
+   the inverse table is built from the inverse roots and L_inverse is 1/len
+*/
+pub fn transform2_staged<T>(mut a: Vec<T>, P: T, table: &[T]) -> Vec<T>
+where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
+{
+	bit_reverse2(&mut a);
+	DFT_radix2_staged(&mut a, P, table);
+	a
+}
+
+pub fn transform3_staged<T>(mut a: Vec<T>, P: T, table: &[T]) -> Vec<T>
+where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
+{
+	bit_reverse3(&mut a);
+	DFT_radix3_staged(&mut a, P, table);
+	a
+}
+
+pub fn inverse2_staged<T>(mut b: Vec<T>, P: T, table: &[T], L_inverse: T) -> Vec<T>
+where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
+{
+	bit_reverse2(&mut b);
+	DFT_radix2_staged(&mut b, P, table);
+	for x in b.iter_mut() {
+		*x = *x * L_inverse % P;
+	}
+	b
+}
+
 //in-place, use mutable reference
+//builds the stage table on every call, repeated transforms take the _staged ones
 pub fn DFT_radix2<T>(a: &mut Vec<T>, P: T, rootTable: &Vec<T>)
 where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
+{
+	let table = stage_table2(&rootTable[..a.len()]);
+	DFT_radix2_staged(a, P, &table);
+}
+
+// twiddles laid out per stage, see twiddle.rs
+pub fn DFT_radix2_staged<T>(a: &mut [T], P: T, table: &[T])
+where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 {
 	let L = a.len();
-	let L_bitNum = (L as f64).log2().trunc() as usize;
 
     //Cooley-Tukey DFT
-	for s in 1..(L_bitNum + 1) {
-		let m = pow(2, s) as usize;
-		let mut i = 0;
-		while i < L {
-			let mut j = 0;
-			while j < m/2 {
-				let t = rootTable[j*(L/m as usize)] * a[i + j + m/2 ] % P;
-				let u = a[i + j] % P;
-				a[i + j] = (u + t) % P;
-				if u <= t {
-					a[i + j + m/2] = ((P + u) - t) % P;
-				} else {
-					a[i + j + m/2] = (u - t) % P;
-				}
-				j+= 1;
+	let mut m = 2;
+	while m <= L {
+		radix2_stage(a, P, table, m);
+		m *= 2;
+	}
+}
+
+// the butterflies of span m, in place
+pub fn radix2_stage<T>(a: &mut [T], P: T, table: &[T], m: usize)
+where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
+{
+	let L = a.len();
+	let twiddles = &table[stage_offset2(m)..];
+	let mut i = 0;
+	while i < L {
+		for j in 0..m/2 {
+			let t = twiddles[j] * a[i + j + m/2 ] % P;
+			let u = a[i + j] % P;
+			a[i + j] = (u + t) % P;
+			if u <= t {
+				a[i + j + m/2] = ((P + u) - t) % P;
+			} else {
+				a[i + j + m/2] = (u - t) % P;
 			}
-			i += m;
 		}
+		i += m;
 	}
 }
 
 pub fn DFT_radix3<T>(a: &mut Vec<T>, P: T, rootTable: &Vec<T>) 
 where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
+{
+	let table = stage_table3(&rootTable[..a.len()]);
+	DFT_radix3_staged(a, P, &table);
+}
+
+pub fn DFT_radix3_staged<T>(a: &mut [T], P: T, table: &[T]) 
+where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 {	
 	let L = a.len();
-	let w = rootTable[L/3];
-	let w_sqr = rootTable[L/3*2];
-
 	let mut i = 1;
 	while i < L {
-		let jump = 3 * i;
-		let stride = L/jump;
-		for j in 0..i {
-			let mut pair = j;
-			while pair < L {
-				let (x, y, z) = (a[pair],
-								a[pair + i] * rootTable[j * stride] % P,
-								a[pair + 2 * i] * rootTable[2 * j * stride] %P);
-				a[pair] 	  	= (x + y + z) % P;
-				a[pair + i]   	= (x % P + w * y % P + w_sqr * z % P) % P;
-                a[pair + 2 * i] = (x % P + w_sqr * y % P + w * z % P) % P;
-				
-				pair += jump;
-			}
+		radix3_stage(a, P, table, i);
+		i *= 3;
+	}
+}
+
+// the butterflies of span 3i, in place
+pub fn radix3_stage<T>(a: &mut [T], P: T, table: &[T], i: usize)
+where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
+{
+	let L = a.len();
+	let w = table[L - 1];
+	let w_sqr = table[L];
+	let jump = 3 * i;
+	let twiddles = &table[stage_offset3(i)..];
+	for j in 0..i {
+		let (w1, w2) = (twiddles[2 * j], twiddles[2 * j + 1]);
+		let mut pair = j;
+		while pair < L {
+			let (x, y, z) = (a[pair],
+							a[pair + i] * w1 % P,
+							a[pair + 2 * i] * w2 %P);
+			a[pair] 	  	= (x + y + z) % P;
+			a[pair + i]   	= (x % P + w * y % P + w_sqr * z % P) % P;
+			a[pair + 2 * i] = (x % P + w_sqr * y % P + w * z % P) % P;
+			
+			pair += jump;
 		}
-		i = jump;
 	}
 }
 
@@ -212,9 +274,9 @@ where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
 				d = d % P;
 			}
 		}
-		d = d.modpow(P - 2.into(), P);
 		denominators.push(d);
 	}
+	let denominators = batch_inverse(&denominators, P);
 
 	let mut evals: Vec<T> = Vec::new();
 	for r in roots {
@@ -0,0 +1,88 @@
+use core::fmt::Debug;
+use std::cmp::PartialOrd;
+use num_traits::*;
+
+use crate::poly::SubproductTree;
+use crate::util::*;
+
+/* Lagrange coefficients from a fixed set of share points
+   to the secret roots, stored row major:
+   coeffs[k * M + i] = l_i(roots[k])
+   so that secret_k = sum_i coeffs[k * M + i] * share_i
+*/
+#[derive(Clone, Debug)]
+pub struct ReconstructionPlan<T> {
+	prime: T,
+	points: Vec<T>,
+	coeffs: Vec<T>,
+	rows: usize,
+}
+
+impl<T: ModPow> ReconstructionPlan<T>
+where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
+{
+	pub fn new(points: &[T], roots: &[T], P: T) -> ReconstructionPlan<T> {
+		let M = points.len();
+		let tree = SubproductTree::new(points, P);
+		let weights = tree.weights();
+		let m = tree.root();
+
+		/* l_i(r) = m(r) / ((r - x_i) * m'(x_i)),
+		unless r is one of the points, then l_i(r) is 0 or 1
+		*/
+		let mut coeffs = vec![T::zero(); roots.len() * M];
+		for (k, r) in roots.iter().enumerate() {
+			let row = &mut coeffs[k * M..(k + 1) * M];
+			if let Some(i) = points.iter().position(|x| x == r) {
+				row[i] = T::one();
+				continue;
+			}
+			let mut m_r = T::zero();
+			for c in m.iter().rev() {
+				m_r = (m_r * *r + *c) % P;
+			}
+			let diffs: Vec<T> = points.iter()
+				.map(|x| if *r >= *x { *r - *x } else { *r + P - *x })
+				.collect();
+			let diff_invs = batch_inverse(&diffs, P);
+			for i in 0..M {
+				row[i] = m_r * diff_invs[i] % P * weights[i] % P;
+			}
+		}
+
+		ReconstructionPlan {
+			prime: P,
+			points: points.to_vec(),
+			coeffs,
+			rows: roots.len(),
+		}
+	}
+
+	pub fn points(&self) -> &[T] {
+		&self.points
+	}
+
+	pub fn coeffs(&self) -> &[T] {
+		&self.coeffs
+	}
+
+	pub fn rows(&self) -> usize {
+		self.rows
+	}
+
+	// matrix-vector product, values ordered as points
+	pub fn apply(&self, values: &[T]) -> Vec<T> {
+		let M = self.points.len();
+		let P = self.prime;
+		assert!(values.len() == M);
+		let mut ret = Vec::with_capacity(self.rows);
+		for row in self.coeffs.chunks(M) {
+			let mut acc = T::zero();
+			for (c, v) in row.iter().zip(values.iter()) {
+				acc = (acc + *c * (*v % P)) % P;
+			}
+			ret.push(acc);
+		}
+		ret
+	}
+}
@@ -0,0 +1,375 @@
+use core::fmt::Debug;
+use std::cmp::PartialOrd;
+use num_traits::*;
+
+use crate::ntt;
+use crate::util::*;
+
+/* Below this length polynomials are multiplied and divided
+   schoolbook style, the NTT setup costs more than it saves
+*/
+const SCHOOLBOOK_LEN: usize = 32;
+
+//polynomials are stored low degree first: [a0, a1, ..., an]
+#[derive(Clone, Debug)]
+pub struct PolyRing<T> {
+	prime: T,
+	// primitive 2^k-th root of unity of the largest power of two dividing P-1
+	root: T,
+	root_order: usize,
+}
+
+impl<T: ModPow> PolyRing<T>
+where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
+{
+	pub fn new(P: T) -> PolyRing<T> {
+		let (root, root_order) = two_adic_root(P);
+		PolyRing {
+			prime: P,
+			root,
+			root_order,
+		}
+	}
+
+	pub fn mul(&self, a: &[T], b: &[T]) -> Vec<T> {
+		if a.is_empty() || b.is_empty() {
+			return Vec::new();
+		}
+		let P = self.prime;
+		let len = a.len() + b.len() - 1;
+		let n = len.next_power_of_two();
+		if a.len().min(b.len()) < SCHOOLBOOK_LEN || n > self.root_order {
+			let mut c = vec![T::zero(); len];
+			for (i, x) in a.iter().enumerate() {
+				for (j, y) in b.iter().enumerate() {
+					c[i + j] = (c[i + j] + *x * *y % P) % P;
+				}
+			}
+			return c;
+		}
+
+		/* Pad both operands to n, multiply pointwise
+		in the evaluation domain and transform back
+		*/
+		let rootTable = self.root_table(n);
+		let mut a_pad = a.to_vec();
+		a_pad.resize(n, T::zero());
+		let mut b_pad = b.to_vec();
+		b_pad.resize(n, T::zero());
+		let a_eval = ntt::transform2(a_pad, P, &rootTable);
+		let b_eval = ntt::transform2(b_pad, P, &rootTable);
+		let c_eval: Vec<T> = a_eval.iter().zip(b_eval.iter())
+			.map(|(x, y)| *x * *y % P)
+			.collect();
+		let mut c = ntt::inverse2(c_eval, P, &rootTable);
+		c.truncate(len);
+		c
+	}
+
+	// 1/a mod x^k, requires a[0] != 0
+	pub fn inverse_series(&self, a: &[T], k: usize) -> Vec<T> {
+		let P = self.prime;
+		let two: T = 2.into();
+		let mut b = vec![a[0].modpow(P - two, P)];
+		let mut len = 1;
+		while len < k {
+			len = (2 * len).min(k);
+			// Newton step: b = b * (2 - a * b) mod x^len
+			let mut e = self.mul(&a[..a.len().min(len)], &b);
+			e.truncate(len);
+			for c in e.iter_mut() {
+				*c = mod_sub(T::zero(), *c, P);
+			}
+			e[0] = (e[0] + two) % P;
+			b = self.mul(&b, &e);
+			b.truncate(len);
+		}
+		b
+	}
+
+	// f mod g, g has to be monic
+	pub fn rem(&self, f: &[T], g: &[T]) -> Vec<T> {
+		let P = self.prime;
+		let m = g.len() - 1;
+		if f.len() <= m {
+			return f.to_vec();
+		}
+		let k = f.len() - m;
+
+		if m < SCHOOLBOOK_LEN || k < SCHOOLBOOK_LEN {
+			let mut r = f.to_vec();
+			for i in (m..f.len()).rev() {
+				let q = r[i];
+				if q == T::zero() {
+					continue;
+				}
+				for j in 0..m {
+					r[i - m + j] = mod_sub(r[i - m + j], q * g[j] % P, P);
+				}
+			}
+			r.truncate(m);
+			return r;
+		}
+
+		/* rev(q) = rev(f) / rev(g) mod x^k
+		rev(g) has constant term 1 because g is monic
+		*/
+		let f_rev: Vec<T> = f.iter().rev().take(k).cloned().collect();
+		let g_rev: Vec<T> = g.iter().rev().cloned().collect();
+		let g_rev_inv = self.inverse_series(&g_rev, k);
+		let mut q = self.mul(&f_rev, &g_rev_inv);
+		q.truncate(k);
+		q.reverse();
+
+		let qg = self.mul(&q, g);
+		let mut r = Vec::with_capacity(m);
+		for i in 0..m {
+			r.push(mod_sub(f[i], qg[i], P));
+		}
+		r
+	}
+
+	fn root_table(&self, n: usize) -> Vec<T> {
+		let P = self.prime;
+		let w = self.root.modpow(((self.root_order / n) as u64).into(), P);
+		let mut table = Vec::with_capacity(n);
+		let mut wi = T::one();
+		for _ in 0..n {
+			table.push(wi);
+			wi = wi * w % P;
+		}
+		table
+	}
+}
+
+/* Subproduct tree over a fixed set of points
+   levels[0] holds the leaves (x - x_i), levels.last() the root
+   prod (x - x_i); a node without sibling is carried up unchanged
+*/
+#[derive(Clone, Debug)]
+pub struct SubproductTree<T> {
+	ring: PolyRing<T>,
+	points: Vec<T>,
+	levels: Vec<Vec<Vec<T>>>,
+	// 1 / prod_{j != i} (x_i - x_j)
+	weights: Vec<T>,
+}
+
+impl<T: ModPow> SubproductTree<T>
+where T: Unsigned + Copy + Debug + From<u64> + PartialOrd
+{
+	pub fn new(points: &[T], P: T) -> SubproductTree<T> {
+		assert!(!points.is_empty());
+		let ring = PolyRing::new(P);
+
+		let mut levels: Vec<Vec<Vec<T>>> = Vec::new();
+		levels.push(points.iter().map(|x| vec![mod_sub(T::zero(), *x, P), T::one()]).collect());
+		while levels.last().unwrap().len() > 1 {
+			let below = levels.last().unwrap();
+			let mut level = Vec::with_capacity(below.len().div_ceil(2));
+			for pair in below.chunks(2) {
+				if pair.len() == 2 {
+					level.push(ring.mul(&pair[0], &pair[1]));
+				} else {
+					level.push(pair[0].clone());
+				}
+			}
+			levels.push(level);
+		}
+
+		let mut tree = SubproductTree {
+			ring,
+			points: points.to_vec(),
+			levels,
+			weights: Vec::new(),
+		};
+
+		/* prod_{j != i} (x_i - x_j) = m'(x_i)
+		where m is the root of the tree
+		*/
+		let root = &tree.levels.last().unwrap()[0];
+		let derivative: Vec<T> = root.iter().enumerate().skip(1)
+			.map(|(i, c)| *c * (i as u64).into() % P)
+			.collect();
+		tree.weights = batch_inverse(&tree.evaluate(&derivative), P);
+		tree
+	}
+
+	pub fn points(&self) -> &[T] {
+		&self.points
+	}
+
+	// 1 / m'(x_i) for every point
+	pub fn weights(&self) -> &[T] {
+		&self.weights
+	}
+
+	// product of all (x - x_i)
+	pub fn root(&self) -> &[T] {
+		&self.levels.last().unwrap()[0]
+	}
+
+	/* Evaluate poly at every point of the tree
+	   by reducing it down the remainder tree
+	*/
+	pub fn evaluate(&self, poly: &[T]) -> Vec<T> {
+		let P = self.prime();
+		let depth = self.levels.len();
+		let reduced: Vec<T> = poly.iter().map(|c| *c % P).collect();
+		let mut rems = vec![self.ring.rem(&reduced, self.root())];
+		for l in (0..depth - 1).rev() {
+			let level = &self.levels[l];
+			let mut next = Vec::with_capacity(level.len());
+			for (k, r) in rems.iter().enumerate() {
+				next.push(self.ring.rem(r, &level[2 * k]));
+				if 2 * k + 1 < level.len() {
+					next.push(self.ring.rem(r, &level[2 * k + 1]));
+				}
+			}
+			rems = next;
+		}
+		rems.into_iter()
+			.map(|r| if r.is_empty() { T::zero() } else { r[0] })
+			.collect()
+	}
+
+	/* Coefficients of the unique poly of degree < points.len()
+	   through (x_i, values_i), combined bottom up as
+	   f = f_left * m_right + f_right * m_left
+	*/
+	pub fn interpolate(&self, values: &[T]) -> Vec<T> {
+		assert!(values.len() == self.points.len());
+		let P = self.prime();
+		let mut polys: Vec<Vec<T>> = values.iter().zip(self.weights.iter())
+			.map(|(y, w)| vec![*y % P * *w % P])
+			.collect();
+		for l in 0..self.levels.len() - 1 {
+			let level = &self.levels[l];
+			let mut next = Vec::with_capacity(polys.len().div_ceil(2));
+			for k in 0..polys.len().div_ceil(2) {
+				if 2 * k + 1 < polys.len() {
+					let left = self.ring.mul(&polys[2 * k], &level[2 * k + 1]);
+					let right = self.ring.mul(&polys[2 * k + 1], &level[2 * k]);
+					let mut sum = vec![T::zero(); left.len().max(right.len())];
+					for (i, c) in left.iter().enumerate() {
+						sum[i] = *c;
+					}
+					for (i, c) in right.iter().enumerate() {
+						sum[i] = (sum[i] + *c) % P;
+					}
+					next.push(sum);
+				} else {
+					next.push(polys[2 * k].clone());
+				}
+			}
+			polys = next;
+		}
+		let mut poly = polys.pop().unwrap();
+		poly.resize(self.points.len(), T::zero());
+		poly
+	}
+
+	fn prime(&self) -> T {
+		self.ring.prime
+	}
+}
+
+pub fn multipoint_evaluation<T>(poly: &[T], points: &[T], P: T) -> Vec<T>
+where T: ModPow + Unsigned + Copy + Debug + From<u64> + PartialOrd
+{
+	SubproductTree::new(points, P).evaluate(poly)
+}
+
+pub fn fast_interpolation<T>(points: &[T], values: &[T], P: T) -> Vec<T>
+where T: ModPow + Unsigned + Copy + Debug + From<u64> + PartialOrd
+{
+	SubproductTree::new(points, P).interpolate(values)
+}
+
+/* Find the largest 2^k dividing P-1 and a primitive 2^k-th root,
+   the (P-1)/2^k power of a quadratic non-residue has exact order 2^k
+*/
+fn two_adic_root<T>(P: T) -> (T, usize)
+where T: ModPow + Unsigned + Copy + Debug + From<u64> + PartialOrd
+{
+	let two: T = 2.into();
+	let mut odd = P - T::one();
+	let mut order = 1usize;
+	while odd % two == T::zero() {
+		odd = odd / two;
+		order *= 2;
+	}
+	let minus_one = P - T::one();
+	let half = minus_one / two;
+	let mut g: T = two;
+	while g.modpow(half, P) != minus_one {
+		g = g + T::one();
+	}
+	(g.modpow(odd, P), order)
+}
+
+fn mod_sub<T>(a: T, b: T, P: T) -> T
+where T: Unsigned + Copy + PartialOrd
+{
+	if a >= b {
+		a - b
+	} else {
+		a + P - b
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::Rng;
+
+	fn horner<T>(poly: &[T], x: T, P: T) -> T
+	where T: Unsigned + Copy
+	{
+		poly.iter().rev().fold(T::zero(), |acc, c| (acc * x + *c) % P)
+	}
+
+	// large enough that the upper levels of the tree go through the NTT
+	fn check_roundtrip<T>(P: T, n: usize)
+	where T: ModPow + Unsigned + Copy + Debug + From<u64> + PartialOrd
+	{
+		let mut rng = rand::thread_rng();
+		let points: Vec<T> = (0..n as u64).map(|i| (7 * i + 3).into()).collect();
+		let poly: Vec<T> = (0..n).map(|_| rng.gen_range(0u64, 1 << 31).into()).collect();
+
+		let tree = SubproductTree::new(&points, P);
+		let values = tree.evaluate(&poly);
+		for (x, y) in points.iter().zip(values.iter()) {
+			assert_eq!(*y, horner(&poly, *x, P));
+		}
+		assert_eq!(tree.interpolate(&values), poly);
+		assert_eq!(fast_interpolation(&points, &multipoint_evaluation(&poly, &points, P), P), poly);
+	}
+
+	#[test]
+	fn interpolate_evaluate_u64() {
+		check_roundtrip(3224862721u64, 1);
+		check_roundtrip(3224862721u64, 37);
+		check_roundtrip(3224862721u64, 300);
+	}
+
+	#[test]
+	fn interpolate_evaluate_u128() {
+		check_roundtrip(4610415792919412737u128, 37);
+		check_roundtrip(4610415792919412737u128, 300);
+	}
+
+	#[test]
+	fn inverse_series_u64() {
+		let P = 3224862721u64;
+		let ring = PolyRing::new(P);
+		let mut rng = rand::thread_rng();
+		let mut a: Vec<u64> = (0..200).map(|_| rng.gen_range(0, P)).collect();
+		a[0] = 5;
+		let mut one = ring.mul(&a, &ring.inverse_series(&a, 150));
+		one.truncate(150);
+		let mut expected = vec![0u64; 150];
+		expected[0] = 1;
+		assert_eq!(one, expected);
+	}
+}
@@ -0,0 +1,710 @@
+/* Batched NTT over several polynomials at once, one polynomial per vector lane.
+   Data is interleaved by lane: coefficient k of lane l sits at a[k * LANES + l].
+   Twiddles are kept in Montgomery form so every butterfly is one Montgomery
+   product on plain values: mont(x, w * R) = x * w mod P.
+   Primes below 2^32 use R = 2^32, primes below 2^63 use R = 2^64.
+*/
+
+use crate::ntt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+	Scalar,
+	Avx2,
+	Neon,
+}
+
+impl Backend {
+	// best vector unit of the running cpu
+	pub fn detect() -> Backend {
+		#[cfg(target_arch = "x86_64")]
+		{
+			if is_x86_feature_detected!("avx2") {
+				return Backend::Avx2;
+			}
+		}
+		#[cfg(target_arch = "aarch64")]
+		{
+			if std::arch::is_aarch64_feature_detected!("neon") {
+				return Backend::Neon;
+			}
+		}
+		Backend::Scalar
+	}
+
+	pub fn supported(&self) -> bool {
+		match self {
+			Backend::Scalar => true,
+			#[cfg(target_arch = "x86_64")]
+			Backend::Avx2 => is_x86_feature_detected!("avx2"),
+			#[cfg(target_arch = "aarch64")]
+			Backend::Neon => std::arch::is_aarch64_feature_detected!("neon"),
+			#[allow(unreachable_patterns)]
+			_ => false,
+		}
+	}
+
+	pub fn lanes(&self) -> usize {
+		match self {
+			Backend::Scalar => 1,
+			Backend::Avx2 => 4,
+			Backend::Neon => 2,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Width {
+	Bits32,
+	Bits64,
+}
+
+#[derive(Clone, Debug)]
+pub struct BatchNtt {
+	prime: u64,
+	backend: Backend,
+	width: Width,
+	// P^-1 mod R
+	pinv: u64,
+
+	// Montgomery form tables
+	roots2: Vec<u64>,
+	inv_roots2: Vec<u64>,
+	inv_L2: u64,
+	roots3: Vec<u64>,
+	inv_roots3: Vec<u64>,
+	inv_L3: u64,
+
+	// bit reversal permutations of both transform sizes
+	perm2: Vec<usize>,
+	perm3: Vec<usize>,
+}
+
+impl BatchNtt {
+
+	/* None if the cpu has no supported vector unit
+	or the prime does not fit the 64-bit lanes,
+	callers then stay on the scalar ntt
+	*/
+	pub fn new(prime: u64, rootTable2: &[u64], rootTable3: &[u64]) -> Option<BatchNtt> {
+		BatchNtt::with_backend(Backend::detect(), prime, rootTable2, rootTable3)
+	}
+
+	pub fn with_backend(backend: Backend, prime: u64, rootTable2: &[u64], rootTable3: &[u64]) -> Option<BatchNtt> {
+		if backend == Backend::Scalar || !backend.supported() || prime >= 1 << 63 || prime.is_multiple_of(2) {
+			return None;
+		}
+		let width = if prime < 1 << 32 { Width::Bits32 } else { Width::Bits64 };
+		let P = prime as u128;
+		let R_bits = match width {
+			Width::Bits32 => 32,
+			Width::Bits64 => 64,
+		};
+		let to_mont = |x: u64| (((x as u128) << R_bits) % P) as u64;
+		let inverse = |x: u64| {
+			let x = x as u128;
+			let (mut base, mut exp, mut res) = (x % P, P - 2, 1u128);
+			while exp > 0 {
+				if exp & 1 == 1 {
+					res = res * base % P;
+				}
+				base = base * base % P;
+				exp >>= 1;
+			}
+			res as u64
+		};
+		// inverse roots in reversed order: w^-i = w^(L-i)
+		let invert_table = |table: &[u64]| -> Vec<u64> {
+			(0..table.len()).map(|i| table[(table.len() - i) % table.len()]).collect()
+		};
+
+		// Newton iteration doubles the correct low bits of P^-1 each round
+		let mut pinv = prime;
+		for _ in 0..6 {
+			pinv = pinv.wrapping_mul(2u64.wrapping_sub(prime.wrapping_mul(pinv)));
+		}
+		if width == Width::Bits32 {
+			pinv &= 0xffff_ffff;
+		}
+
+		let L2 = rootTable2.len();
+		let L3 = rootTable3.len();
+		Some(BatchNtt {
+			prime,
+			backend,
+			width,
+			pinv,
+
+			roots2: rootTable2.iter().map(|w| to_mont(*w)).collect(),
+			inv_roots2: invert_table(rootTable2).into_iter().map(to_mont).collect(),
+			inv_L2: to_mont(inverse(L2 as u64)),
+			roots3: rootTable3.iter().map(|w| to_mont(*w)).collect(),
+			inv_roots3: invert_table(rootTable3).into_iter().map(to_mont).collect(),
+			inv_L3: to_mont(inverse(L3 as u64)),
+
+			perm2: permutation(L2, ntt::bit_reverse2),
+			perm3: permutation(L3, ntt::bit_reverse3),
+		})
+	}
+
+	pub fn backend(&self) -> Backend {
+		self.backend
+	}
+
+	pub fn lanes(&self) -> usize {
+		self.backend.lanes()
+	}
+
+	// polys of degree2 values
+	pub fn transform2(&self, polys: &mut [Vec<u64>]) {
+		self.batched(polys, &self.perm2, &[Pass::Radix2(&self.roots2)]);
+	}
+
+	pub fn inverse2(&self, polys: &mut [Vec<u64>]) {
+		self.batched(polys, &self.perm2, &[Pass::Radix2(&self.inv_roots2), Pass::Scale(self.inv_L2)]);
+	}
+
+	// polys of degree3 values
+	pub fn transform3(&self, polys: &mut [Vec<u64>]) {
+		self.batched(polys, &self.perm3, &[Pass::Radix3(&self.roots3)]);
+	}
+
+	pub fn inverse3(&self, polys: &mut [Vec<u64>]) {
+		self.batched(polys, &self.perm3, &[Pass::Radix3(&self.inv_roots3), Pass::Scale(self.inv_L3)]);
+	}
+
+	/* inverse2 -> zero pad to degree3 -> transform3 for every block,
+	   blocks have degree2 values, returned shares degree3 values
+	*/
+	pub fn share_blocks(&self, blocks: &[Vec<u64>]) -> Vec<Vec<u64>> {
+		let n = self.lanes();
+		let L2 = self.perm2.len();
+		let L3 = self.perm3.len();
+		let mut ret = Vec::with_capacity(blocks.len());
+		for group in blocks.chunks(n) {
+			let mut a = self.interleave(group, L2);
+			self.bit_reverse(&mut a, &self.perm2);
+			self.run(&mut a, L2, &[Pass::Radix2(&self.inv_roots2), Pass::Scale(self.inv_L2)]);
+			// interleaved layout keeps the low coefficients contiguous
+			a.resize(L3 * n, 0);
+			self.bit_reverse(&mut a, &self.perm3);
+			self.run(&mut a, L3, &[Pass::Radix3(&self.roots3)]);
+			ret.extend(self.deinterleave(&a, group.len(), L3));
+		}
+		ret
+	}
+
+	fn batched(&self, polys: &mut [Vec<u64>], perm: &[usize], passes: &[Pass]) {
+		let n = self.lanes();
+		let L = perm.len();
+		for group in polys.chunks_mut(n) {
+			let mut a = self.interleave(group, L);
+			self.bit_reverse(&mut a, perm);
+			self.run(&mut a, L, passes);
+			for (l, poly) in self.deinterleave(&a, group.len(), L).into_iter().enumerate() {
+				group[l] = poly;
+			}
+		}
+	}
+
+	// missing lanes of the last group are zero polynomials
+	fn interleave(&self, group: &[Vec<u64>], L: usize) -> Vec<u64> {
+		let n = self.lanes();
+		let mut a = vec![0u64; L * n];
+		for (l, poly) in group.iter().enumerate() {
+			assert!(poly.len() == L);
+			for k in 0..L {
+				a[k * n + l] = poly[k] % self.prime;
+			}
+		}
+		a
+	}
+
+	fn deinterleave(&self, a: &[u64], count: usize, L: usize) -> Vec<Vec<u64>> {
+		let n = self.lanes();
+		(0..count).map(|l| (0..L).map(|k| a[k * n + l]).collect()).collect()
+	}
+
+	fn bit_reverse(&self, a: &mut [u64], perm: &[usize]) {
+		let n = self.lanes();
+		for (i, t) in perm.iter().enumerate() {
+			if *t > i {
+				for l in 0..n {
+					a.swap(i * n + l, *t * n + l);
+				}
+			}
+		}
+	}
+
+	fn run(&self, a: &mut [u64], L: usize, passes: &[Pass]) {
+		let (p, pinv) = (self.prime, self.pinv);
+		unsafe {
+			match (self.backend, self.width) {
+				#[cfg(target_arch = "x86_64")]
+				(Backend::Avx2, Width::Bits32) => avx2::run32(a, L, passes, p, pinv),
+				#[cfg(target_arch = "x86_64")]
+				(Backend::Avx2, Width::Bits64) => avx2::run64(a, L, passes, p, pinv),
+				#[cfg(target_arch = "aarch64")]
+				(Backend::Neon, Width::Bits32) => neon::run32(a, L, passes, p, pinv),
+				#[cfg(target_arch = "aarch64")]
+				(Backend::Neon, Width::Bits64) => neon::run64(a, L, passes, p, pinv),
+				_ => unreachable!("no vector backend {:?}", self.backend),
+			}
+		}
+	}
+}
+
+enum Pass<'a> {
+	// Cooley-Tukey DFT with the given root table, as ntt::DFT_radix2
+	Radix2(&'a [u64]),
+	// as ntt::DFT_radix3
+	Radix3(&'a [u64]),
+	// multiply every value by a constant in Montgomery form
+	Scale(u64),
+}
+
+fn permutation(L: usize, bit_reverse: fn(&mut Vec<usize>)) -> Vec<usize> {
+	let mut perm: Vec<usize> = (0..L).collect();
+	bit_reverse(&mut perm);
+	perm
+}
+
+/* Modular vector arithmetic on values < P, one implementation per
+   backend and prime width. Everything is inline(always) so that it ends up
+   inside the target_feature entry points of the backend.
+*/
+trait Lanes {
+	type V: Copy;
+	const LANES: usize;
+
+	unsafe fn load(src: *const u64) -> Self::V;
+	unsafe fn store(dst: *mut u64, v: Self::V);
+	unsafe fn splat(x: u64) -> Self::V;
+	unsafe fn add_mod(a: Self::V, b: Self::V, p: Self::V) -> Self::V;
+	unsafe fn sub_mod(a: Self::V, b: Self::V, p: Self::V) -> Self::V;
+	// a * b / R mod P
+	unsafe fn mont_mul(a: Self::V, b: Self::V, p: Self::V, pinv: Self::V) -> Self::V;
+}
+
+#[inline(always)]
+#[allow(dead_code)]
+unsafe fn run_passes<O: Lanes>(a: &mut [u64], L: usize, passes: &[Pass], p: u64, pinv: u64) {
+	let n = O::LANES;
+	let ptr = a.as_mut_ptr();
+	let p = O::splat(p);
+	let pinv = O::splat(pinv);
+
+	for pass in passes {
+		match pass {
+			Pass::Radix2(roots) => {
+				let mut m = 2;
+				while m <= L {
+					for j in 0..m / 2 {
+						let w = O::splat(roots[j * (L / m)]);
+						let mut i = 0;
+						while i < L {
+							let lo = ptr.add((i + j) * n);
+							let hi = ptr.add((i + j + m / 2) * n);
+							let t = O::mont_mul(O::load(hi), w, p, pinv);
+							let u = O::load(lo);
+							O::store(lo, O::add_mod(u, t, p));
+							O::store(hi, O::sub_mod(u, t, p));
+							i += m;
+						}
+					}
+					m *= 2;
+				}
+			}
+			Pass::Radix3(roots) => {
+				let w = O::splat(roots[L / 3]);
+				let w_sqr = O::splat(roots[L / 3 * 2]);
+				let mut i = 1;
+				while i < L {
+					let jump = 3 * i;
+					let stride = L / jump;
+					for j in 0..i {
+						let w1 = O::splat(roots[j * stride]);
+						let w2 = O::splat(roots[2 * j * stride]);
+						let mut pair = j;
+						while pair < L {
+							let px = ptr.add(pair * n);
+							let py = ptr.add((pair + i) * n);
+							let pz = ptr.add((pair + 2 * i) * n);
+							let x = O::load(px);
+							let y = O::mont_mul(O::load(py), w1, p, pinv);
+							let z = O::mont_mul(O::load(pz), w2, p, pinv);
+							let (wy, wz) = (O::mont_mul(y, w, p, pinv), O::mont_mul(z, w, p, pinv));
+							let (w2y, w2z) = (O::mont_mul(y, w_sqr, p, pinv), O::mont_mul(z, w_sqr, p, pinv));
+							O::store(px, O::add_mod(x, O::add_mod(y, z, p), p));
+							O::store(py, O::add_mod(x, O::add_mod(wy, w2z, p), p));
+							O::store(pz, O::add_mod(x, O::add_mod(w2y, wz, p), p));
+							pair += jump;
+						}
+					}
+					i = jump;
+				}
+			}
+			Pass::Scale(c) => {
+				let c = O::splat(*c);
+				for k in 0..L {
+					let pk = ptr.add(k * n);
+					O::store(pk, O::mont_mul(O::load(pk), c, p, pinv));
+				}
+			}
+		}
+	}
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+	use std::arch::x86_64::*;
+	use super::{Lanes, Pass, run_passes};
+
+	#[target_feature(enable = "avx2")]
+	pub unsafe fn run32(a: &mut [u64], L: usize, passes: &[Pass], p: u64, pinv: u64) {
+		run_passes::<Mont32>(a, L, passes, p, pinv)
+	}
+
+	#[target_feature(enable = "avx2")]
+	pub unsafe fn run64(a: &mut [u64], L: usize, passes: &[Pass], p: u64, pinv: u64) {
+		run_passes::<Mont64>(a, L, passes, p, pinv)
+	}
+
+	// unsigned a > b, avx2 only compares signed 64-bit lanes
+	#[inline(always)]
+	unsafe fn cmpgt(a: __m256i, b: __m256i) -> __m256i {
+		let sign = _mm256_set1_epi64x(i64::MIN);
+		_mm256_cmpgt_epi64(_mm256_xor_si256(a, sign), _mm256_xor_si256(b, sign))
+	}
+
+	#[inline(always)]
+	unsafe fn add_mod(a: __m256i, b: __m256i, p: __m256i) -> __m256i {
+		let s = _mm256_add_epi64(a, b);
+		let keep = cmpgt(p, s);
+		_mm256_sub_epi64(s, _mm256_andnot_si256(keep, p))
+	}
+
+	#[inline(always)]
+	unsafe fn sub_mod(a: __m256i, b: __m256i, p: __m256i) -> __m256i {
+		let d = _mm256_sub_epi64(a, b);
+		let borrow = cmpgt(b, a);
+		_mm256_add_epi64(d, _mm256_and_si256(borrow, p))
+	}
+
+	// full 64x64 -> 128 product from four 32x32 products, (hi, lo)
+	#[inline(always)]
+	unsafe fn mul_wide(a: __m256i, b: __m256i) -> (__m256i, __m256i) {
+		let mask = _mm256_set1_epi64x(0xffff_ffff);
+		let a_hi = _mm256_srli_epi64(a, 32);
+		let b_hi = _mm256_srli_epi64(b, 32);
+		let ll = _mm256_mul_epu32(a, b);
+		let lh = _mm256_mul_epu32(a, b_hi);
+		let hl = _mm256_mul_epu32(a_hi, b);
+		let hh = _mm256_mul_epu32(a_hi, b_hi);
+		let mid = _mm256_add_epi64(
+			_mm256_add_epi64(_mm256_srli_epi64(ll, 32), _mm256_and_si256(lh, mask)),
+			_mm256_and_si256(hl, mask));
+		let lo = _mm256_or_si256(_mm256_and_si256(ll, mask), _mm256_slli_epi64(mid, 32));
+		let hi = _mm256_add_epi64(
+			_mm256_add_epi64(hh, _mm256_srli_epi64(lh, 32)),
+			_mm256_add_epi64(_mm256_srli_epi64(hl, 32), _mm256_srli_epi64(mid, 32)));
+		(hi, lo)
+	}
+
+	// low 64 bits of a * b
+	#[inline(always)]
+	unsafe fn mul_lo(a: __m256i, b: __m256i) -> __m256i {
+		let cross = _mm256_add_epi64(
+			_mm256_mul_epu32(a, _mm256_srli_epi64(b, 32)),
+			_mm256_mul_epu32(_mm256_srli_epi64(a, 32), b));
+		_mm256_add_epi64(_mm256_mul_epu32(a, b), _mm256_slli_epi64(cross, 32))
+	}
+
+	pub struct Mont32;
+
+	impl Lanes for Mont32 {
+		type V = __m256i;
+		const LANES: usize = 4;
+
+		#[inline(always)]
+		unsafe fn load(src: *const u64) -> __m256i {
+			_mm256_loadu_si256(src as *const __m256i)
+		}
+		#[inline(always)]
+		unsafe fn store(dst: *mut u64, v: __m256i) {
+			_mm256_storeu_si256(dst as *mut __m256i, v)
+		}
+		#[inline(always)]
+		unsafe fn splat(x: u64) -> __m256i {
+			_mm256_set1_epi64x(x as i64)
+		}
+		#[inline(always)]
+		unsafe fn add_mod(a: __m256i, b: __m256i, p: __m256i) -> __m256i {
+			add_mod(a, b, p)
+		}
+		#[inline(always)]
+		unsafe fn sub_mod(a: __m256i, b: __m256i, p: __m256i) -> __m256i {
+			sub_mod(a, b, p)
+		}
+		// t = a * b, m = t * P^-1 mod 2^32, (t - m * P) / 2^32 = hi(t) - hi(m * P)
+		#[inline(always)]
+		unsafe fn mont_mul(a: __m256i, b: __m256i, p: __m256i, pinv: __m256i) -> __m256i {
+			let t = _mm256_mul_epu32(a, b);
+			let m = _mm256_mul_epu32(t, pinv);
+			let mp = _mm256_mul_epu32(m, p);
+			sub_mod(_mm256_srli_epi64(t, 32), _mm256_srli_epi64(mp, 32), p)
+		}
+	}
+
+	pub struct Mont64;
+
+	impl Lanes for Mont64 {
+		type V = __m256i;
+		const LANES: usize = 4;
+
+		#[inline(always)]
+		unsafe fn load(src: *const u64) -> __m256i {
+			_mm256_loadu_si256(src as *const __m256i)
+		}
+		#[inline(always)]
+		unsafe fn store(dst: *mut u64, v: __m256i) {
+			_mm256_storeu_si256(dst as *mut __m256i, v)
+		}
+		#[inline(always)]
+		unsafe fn splat(x: u64) -> __m256i {
+			_mm256_set1_epi64x(x as i64)
+		}
+		#[inline(always)]
+		unsafe fn add_mod(a: __m256i, b: __m256i, p: __m256i) -> __m256i {
+			add_mod(a, b, p)
+		}
+		#[inline(always)]
+		unsafe fn sub_mod(a: __m256i, b: __m256i, p: __m256i) -> __m256i {
+			sub_mod(a, b, p)
+		}
+		// same reduction as Mont32 with R = 2^64
+		#[inline(always)]
+		unsafe fn mont_mul(a: __m256i, b: __m256i, p: __m256i, pinv: __m256i) -> __m256i {
+			let (t_hi, t_lo) = mul_wide(a, b);
+			let m = mul_lo(t_lo, pinv);
+			let (mp_hi, _) = mul_wide(m, p);
+			sub_mod(t_hi, mp_hi, p)
+		}
+	}
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+	use std::arch::aarch64::*;
+	use super::{Lanes, Pass, run_passes};
+
+	#[target_feature(enable = "neon")]
+	pub unsafe fn run32(a: &mut [u64], L: usize, passes: &[Pass], p: u64, pinv: u64) {
+		run_passes::<Mont32>(a, L, passes, p, pinv)
+	}
+
+	#[target_feature(enable = "neon")]
+	pub unsafe fn run64(a: &mut [u64], L: usize, passes: &[Pass], p: u64, pinv: u64) {
+		run_passes::<Mont64>(a, L, passes, p, pinv)
+	}
+
+	#[inline(always)]
+	unsafe fn add_mod(a: uint64x2_t, b: uint64x2_t, p: uint64x2_t) -> uint64x2_t {
+		let s = vaddq_u64(a, b);
+		let keep = vcgtq_u64(p, s);
+		vsubq_u64(s, vbicq_u64(p, keep))
+	}
+
+	#[inline(always)]
+	unsafe fn sub_mod(a: uint64x2_t, b: uint64x2_t, p: uint64x2_t) -> uint64x2_t {
+		let d = vsubq_u64(a, b);
+		let borrow = vcgtq_u64(b, a);
+		vaddq_u64(d, vandq_u64(borrow, p))
+	}
+
+	// product of the low 32 bits of every lane
+	#[inline(always)]
+	unsafe fn mul_epu32(a: uint64x2_t, b: uint64x2_t) -> uint64x2_t {
+		vmull_u32(vmovn_u64(a), vmovn_u64(b))
+	}
+
+	#[inline(always)]
+	unsafe fn mul_wide(a: uint64x2_t, b: uint64x2_t) -> (uint64x2_t, uint64x2_t) {
+		let mask = vdupq_n_u64(0xffff_ffff);
+		let a_hi = vshrq_n_u64::<32>(a);
+		let b_hi = vshrq_n_u64::<32>(b);
+		let ll = mul_epu32(a, b);
+		let lh = mul_epu32(a, b_hi);
+		let hl = mul_epu32(a_hi, b);
+		let hh = mul_epu32(a_hi, b_hi);
+		let mid = vaddq_u64(
+			vaddq_u64(vshrq_n_u64::<32>(ll), vandq_u64(lh, mask)),
+			vandq_u64(hl, mask));
+		let lo = vorrq_u64(vandq_u64(ll, mask), vshlq_n_u64::<32>(mid));
+		let hi = vaddq_u64(
+			vaddq_u64(hh, vshrq_n_u64::<32>(lh)),
+			vaddq_u64(vshrq_n_u64::<32>(hl), vshrq_n_u64::<32>(mid)));
+		(hi, lo)
+	}
+
+	#[inline(always)]
+	unsafe fn mul_lo(a: uint64x2_t, b: uint64x2_t) -> uint64x2_t {
+		let cross = vaddq_u64(
+			mul_epu32(a, vshrq_n_u64::<32>(b)),
+			mul_epu32(vshrq_n_u64::<32>(a), b));
+		vaddq_u64(mul_epu32(a, b), vshlq_n_u64::<32>(cross))
+	}
+
+	pub struct Mont32;
+
+	impl Lanes for Mont32 {
+		type V = uint64x2_t;
+		const LANES: usize = 2;
+
+		#[inline(always)]
+		unsafe fn load(src: *const u64) -> uint64x2_t {
+			vld1q_u64(src)
+		}
+		#[inline(always)]
+		unsafe fn store(dst: *mut u64, v: uint64x2_t) {
+			vst1q_u64(dst, v)
+		}
+		#[inline(always)]
+		unsafe fn splat(x: u64) -> uint64x2_t {
+			vdupq_n_u64(x)
+		}
+		#[inline(always)]
+		unsafe fn add_mod(a: uint64x2_t, b: uint64x2_t, p: uint64x2_t) -> uint64x2_t {
+			add_mod(a, b, p)
+		}
+		#[inline(always)]
+		unsafe fn sub_mod(a: uint64x2_t, b: uint64x2_t, p: uint64x2_t) -> uint64x2_t {
+			sub_mod(a, b, p)
+		}
+		#[inline(always)]
+		unsafe fn mont_mul(a: uint64x2_t, b: uint64x2_t, p: uint64x2_t, pinv: uint64x2_t) -> uint64x2_t {
+			let t = mul_epu32(a, b);
+			let m = mul_epu32(t, pinv);
+			let mp = mul_epu32(m, p);
+			sub_mod(vshrq_n_u64::<32>(t), vshrq_n_u64::<32>(mp), p)
+		}
+	}
+
+	pub struct Mont64;
+
+	impl Lanes for Mont64 {
+		type V = uint64x2_t;
+		const LANES: usize = 2;
+
+		#[inline(always)]
+		unsafe fn load(src: *const u64) -> uint64x2_t {
+			vld1q_u64(src)
+		}
+		#[inline(always)]
+		unsafe fn store(dst: *mut u64, v: uint64x2_t) {
+			vst1q_u64(dst, v)
+		}
+		#[inline(always)]
+		unsafe fn splat(x: u64) -> uint64x2_t {
+			vdupq_n_u64(x)
+		}
+		#[inline(always)]
+		unsafe fn add_mod(a: uint64x2_t, b: uint64x2_t, p: uint64x2_t) -> uint64x2_t {
+			add_mod(a, b, p)
+		}
+		#[inline(always)]
+		unsafe fn sub_mod(a: uint64x2_t, b: uint64x2_t, p: uint64x2_t) -> uint64x2_t {
+			sub_mod(a, b, p)
+		}
+		#[inline(always)]
+		unsafe fn mont_mul(a: uint64x2_t, b: uint64x2_t, p: uint64x2_t, pinv: uint64x2_t) -> uint64x2_t {
+			let (t_hi, t_lo) = mul_wide(a, b);
+			let m = mul_lo(t_lo, pinv);
+			let (mp_hi, _) = mul_wide(m, p);
+			sub_mod(t_hi, mp_hi, p)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::Rng;
+
+	fn root_table(root: u128, L: usize, P: u128) -> Vec<u128> {
+		let mut table = Vec::with_capacity(L);
+		let mut w = 1u128;
+		for _ in 0..L {
+			table.push(w);
+			w = w * root % P;
+		}
+		table
+	}
+
+	// the scalar ntt in u128 is the reference, u64 would overflow on the 62-bit prime
+	fn check_backend(backend: Backend, prime: u64, root2: u64, root3: u64, L2: usize, L3: usize) {
+		let P = prime as u128;
+		let table2 = root_table(root2 as u128, L2, P);
+		let table3 = root_table(root3 as u128, L3, P);
+		let narrow = |table: &[u128]| -> Vec<u64> { table.iter().map(|w| *w as u64).collect() };
+		let widen = |poly: &Vec<u64>| -> Vec<u128> { poly.iter().map(|x| *x as u128).collect() };
+		let batch = BatchNtt::with_backend(backend, prime, &narrow(&table2), &narrow(&table3)).unwrap();
+
+		// one more than a full group so the partial tail is covered too
+		let count = backend.lanes() + 1;
+		let mut rng = rand::thread_rng();
+		let mut random = |L: usize| -> Vec<Vec<u64>> {
+			(0..count).map(|_| (0..L).map(|_| rng.gen_range(0, prime)).collect()).collect()
+		};
+
+		let polys2 = random(L2);
+		let mut a = polys2.clone();
+		batch.transform2(&mut a);
+		let mut b = polys2.clone();
+		batch.inverse2(&mut b);
+		for i in 0..count {
+			assert_eq!(widen(&a[i]), ntt::transform2(widen(&polys2[i]), P, &table2));
+			assert_eq!(widen(&b[i]), ntt::inverse2(widen(&polys2[i]), P, &table2));
+		}
+
+		let polys3 = random(L3);
+		let mut a = polys3.clone();
+		batch.transform3(&mut a);
+		let mut b = polys3.clone();
+		batch.inverse3(&mut b);
+		for i in 0..count {
+			assert_eq!(widen(&a[i]), ntt::transform3(widen(&polys3[i]), P, &table3));
+			assert_eq!(widen(&b[i]), ntt::inverse3(widen(&polys3[i]), P, &table3));
+		}
+
+		let shares = batch.share_blocks(&polys2);
+		for i in 0..count {
+			let mut coeffs = ntt::inverse2(widen(&polys2[i]), P, &table2);
+			coeffs.resize(L3, 0);
+			assert_eq!(widen(&shares[i]), ntt::transform3(coeffs, P, &table3));
+		}
+	}
+
+	fn check(backend: Backend) {
+		if !backend.supported() {
+			return;
+		}
+		check_backend(backend, 3224862721, 889378889, 388768380, 512, 729);
+		check_backend(backend, 4610415792919412737, 1266473570726112470, 2230453091198852918, 512, 729);
+	}
+
+	#[test]
+	fn avx2_matches_scalar() {
+		check(Backend::Avx2);
+	}
+
+	#[test]
+	fn neon_matches_scalar() {
+		check(Backend::Neon);
+	}
+
+	#[test]
+	fn scalar_has_no_batch() {
+		assert!(BatchNtt::with_backend(Backend::Scalar, 3224862721, &[1], &[1]).is_none());
+	}
+}
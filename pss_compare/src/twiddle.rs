@@ -0,0 +1,55 @@
+/* Per-stage twiddle tables, one layout for ntt.rs and the OpenCL kernels
+   so every stage reads its twiddles contiguously from offset 0
+
+   radix 2, L = 2^k, stages m = 2, 4, ..., L:
+	  table[m/2 - 1 + j] = w^(j * L/m)			j < m/2
+	  L - 1 entries, stage m starts at m/2 - 1
+
+   radix 3, L = 3^k, stages with span 3i, i = 1, 3, ..., L/3:
+	  table[i - 1 + 2j]     = w^(j * L/3i)		j < i
+	  table[i - 1 + 2j + 1] = w^(2j * L/3i)
+	  then w^(L/3) at L - 1 and w^(2L/3) at L
+	  L + 1 entries, stage i starts at i - 1
+
+   w is rootTable[1], built from the inverse rootTable
+   the same layout gives the inverse twiddles
+*/
+
+pub fn stage_table2<T: Copy>(rootTable: &[T]) -> Vec<T> {
+	let L = rootTable.len();
+	let mut table = Vec::with_capacity(L.max(1) - 1);
+	let mut m = 2;
+	while m <= L {
+		for j in 0..m/2 {
+			table.push(rootTable[j * (L/m)]);
+		}
+		m *= 2;
+	}
+	table
+}
+
+pub fn stage_table3<T: Copy>(rootTable: &[T]) -> Vec<T> {
+	let L = rootTable.len();
+	let mut table = Vec::with_capacity(L + 1);
+	let mut i = 1;
+	while i < L {
+		let stride = L / (3 * i);
+		for j in 0..i {
+			table.push(rootTable[j * stride]);
+			table.push(rootTable[2 * j * stride]);
+		}
+		i *= 3;
+	}
+	table.push(rootTable[L/3]);
+	table.push(rootTable[L/3*2]);
+	table
+}
+
+// offset of the stage with butterflies of span m (radix 2) or 3i (radix 3)
+pub fn stage_offset2(m: usize) -> usize {
+	m/2 - 1
+}
+
+pub fn stage_offset3(i: usize) -> usize {
+	i - 1
+}
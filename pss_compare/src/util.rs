@@ -1,3 +1,4 @@
+use num_traits::Unsigned;
 
 pub trait ModPow {
     fn modpow(&self, exponent: Self, modulus: Self) -> Self;
@@ -40,6 +41,35 @@ impl ModPow for u32 {
     modpow!(u32);
 }
 
+/* Montgomery's trick: invert all values with one modpow
+   and 3(n-1) multiplications, values have to be non-zero mod P:
+   a zero has no inverse and would zero the whole product, so it panics
+*/
+pub fn batch_inverse<T>(values: &[T], P: T) -> Vec<T>
+where T: ModPow + Unsigned + Copy + From<u64>
+{
+    if values.is_empty() {
+        return Vec::new();
+    }
+    // prefix[i] = values[0] * ... * values[i]
+    let mut prefix = Vec::with_capacity(values.len());
+    let mut acc = T::one();
+    for v in values {
+        acc = acc * (*v % P) % P;
+        prefix.push(acc);
+    }
+    assert!(acc != T::zero(), "batch_inverse of zero");
+
+    let mut inv = acc.modpow(P - 2.into(), P);
+    let mut ret = vec![T::zero(); values.len()];
+    for i in (1..values.len()).rev() {
+        ret[i] = inv * prefix[i - 1] % P;
+        inv = inv * (values[i] % P) % P;
+    }
+    ret[0] = inv;
+    ret
+}
+
 pub trait HasMax {
     fn max() -> Self;
 }
@@ -62,6 +92,32 @@ impl HasMax for u128 {
     }
 }
 
+pub trait AsU64 {
+    fn as_u64(&self) -> Option<u64>;
+}
+
+impl AsU64 for u32 {
+    fn as_u64(&self) -> Option<u64> {
+        Some(*self as u64)
+    }
+}
+
+impl AsU64 for u64 {
+    fn as_u64(&self) -> Option<u64> {
+        Some(*self)
+    }
+}
+
+impl AsU64 for u128 {
+    fn as_u64(&self) -> Option<u64> {
+        if *self <= u64::MAX as u128 {
+            Some(*self as u64)
+        } else {
+            None
+        }
+    }
+}
+
 // impl ModPow for &u128 {
 //     modpow!(u128);
 // }
@@ -91,4 +147,38 @@ impl HasMax for u128 {
 //     fn max() -> &u128 {
 //         &std::u128::MAX
 //     }
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    const P: u64 = 3224862721;
+
+    #[test]
+    fn batch_inverse_inverts() {
+        let mut rng = rand::thread_rng();
+        let values: Vec<u64> = (0..1000).map(|_| rng.gen_range(1, P)).collect();
+        let inverses = batch_inverse(&values, P);
+        assert_eq!(inverses.len(), values.len());
+        for (v, inv) in values.iter().zip(inverses.iter()) {
+            assert_eq!(v * inv % P, 1);
+        }
+    }
+
+    #[test]
+    fn batch_inverse_short() {
+        assert!(batch_inverse::<u64>(&[], P).is_empty());
+        assert_eq!(batch_inverse(&[1u64], P), vec![1]);
+        assert_eq!(batch_inverse(&[P - 1], P), vec![P - 1]);
+        // values are reduced mod P first
+        assert_eq!(batch_inverse(&[P + 2], P)[0] * 2 % P, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "batch_inverse of zero")]
+    fn batch_inverse_zero() {
+        batch_inverse(&[5u64, P, 7], P);
+    }
+}